@@ -128,9 +128,20 @@ async function main() {
           allocations.push(alloc);
       }
   }
-  const policies = {
-    "IMMEDIATE": "Immediate",
-    "BALANCED": "Balanced"
+  // the contract's policy_type for a ledger policy, with its parameters
+  const policyType = policy => {
+    switch (policy.policyType) {
+      case "IMMEDIATE":
+        return "Immediate";
+      case "BALANCED":
+        return "Balanced";
+      case "RECENT":
+        return { Recent: { discount: String(policy.discount) } };
+      case "SPECIAL":
+        return { Special: { memo: policy.memo, recipient: policy.recipient } };
+      default:
+        throw new Error(`Unknown policyType=${policy.policyType}`);
+    }
   }
 
   for (const allocation of allocations) {
@@ -140,38 +151,30 @@ async function main() {
     let tokenInfo = await client.queryContractSmart(tokenAddress, { token_info: { } });
     console.log('tokenInfo result: ', tokenInfo)
 
-    for (const receipt of allocation.receipts) {
-
-      // allocate if user is registered
-      let result = await client.queryContractSmart(contractAddress, { is_cred_registered: { cred_id: receipt.id } });
-
-      if (result.registered) {
-        let allocResult = await client.queryContractSmart(contractAddress, { is_allocated: { cred_id: receipt.id, allocation_id: allocation.id} });
-
-        if (allocResult.allocated) {
-          console.log(`Already allocated=${allocation.id} to id=${receipt.id}`)
-          continue
-        }
+    // unregistered users are escrowed by the contract until they register,
+    // already allocated receipts are skipped
+    const receipts = allocation.receipts.map(receipt => ({ cred_id: receipt.id, amount: receipt.amount }));
 
-        console.log(`allocating ${receipt.amount} to ${receipt.id}`);
+    if (receipts.length === 0) {
+      console.log(`No receipts for allocation=${allocation.id}`)
+      continue
+    }
 
-        const allocateMsg = {
-          allocation_id: allocation.id,
-          cred_id: receipt.id,
-          amount: receipt.amount,
-          policy_type: policies[allocation.policy.policyType],
-        }
-        console.log(`allocation message=${JSON.stringify(allocateMsg)}`)
+    const allocateMsg = {
+      allocation_id: allocation.id,
+      policy_type: policyType(allocation.policy),
+      receipts,
+      mode: "skip",
+    }
+    console.log(`allocation message=${JSON.stringify(allocateMsg)}`)
 
-        let result = await client.execute(
-          contractAddress, {allocate: allocateMsg}
-        );
+    let result = await client.execute(
+      contractAddress, {allocate_batch: allocateMsg}
+    );
 
-        console.info(`Allocate result: ${JSON.stringify(result)}`);
-      }
+    console.info(`Allocate result: ${JSON.stringify(result)}`);
 
-      tokenInfo = await client.queryContractSmart(tokenAddress, { token_info: { } });
-    }
+    tokenInfo = await client.queryContractSmart(tokenAddress, { token_info: { } });
     console.log('tokenInfo result: ', tokenInfo)
   }
 }
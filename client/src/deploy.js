@@ -6,6 +6,7 @@ require('dotenv-defaults').config();
 /* eslint-disable @typescript-eslint/camelcase */
 const { EnigmaUtils, Secp256k1Pen, SigningCosmWasmClient, pubkeyToAddress, encodeSecp256k1Pubkey } = require("secretjs");
 const fs = require("fs");
+const crypto = require("crypto");
 
 //const httpUrl = "http://localhost:1317";
 const httpUrl = "https://bootstrap.secrettestnet.io";
@@ -79,7 +80,9 @@ async function main() {
   const initMsg = {"token_contract": {
     "address": contractAddress,
     "code_hash": hashStr.substring(0, hashStr.indexOf(' ')),
-  }}
+  },
+  "prng_seed": Buffer.from(crypto.randomBytes(32)).toString('base64'),
+  }
 
   const secretCredInit = await client.instantiate(uploadReceipt.codeId, initMsg, label);
   console.info(`Contract instantiated at ${secretCredInit.contractAddress}`);
@@ -3,7 +3,13 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use secretcred::msg::{CredRegisteredResponse, TotalAllocatedResponse, HandleMsg, InitMsg, QueryMsg};
+use secretcred::msg::{
+    AllocationsResponse, CapHeadroomResponse, ClaimableResponse, ContractStatusResponse, CredAllocatedResponse,
+    CredIdsResponse, CredRegisteredResponse, DistributionResponse, DistributionRootResponse, EscrowResponse,
+    HandleAnswer, HandleMsg, InitMsg, ListUsersResponse, PendingOwnerResponse, QueryMsg, RevocationsResponse,
+    RewardTokensResponse, RoleHoldersResponse, TotalAllocatedResponse, TreasuryResponse, UserCredResponse,
+    VestingResponse,
+};
 use secretcred::state::State;
 
 fn main() {
@@ -14,8 +20,26 @@ fn main() {
 
     export_schema(&schema_for!(InitMsg), &out_dir);
     export_schema(&schema_for!(HandleMsg), &out_dir);
+    export_schema(&schema_for!(HandleAnswer), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
     export_schema(&schema_for!(State), &out_dir);
     export_schema(&schema_for!(CredRegisteredResponse), &out_dir);
     export_schema(&schema_for!(TotalAllocatedResponse), &out_dir);
+    export_schema(&schema_for!(CredAllocatedResponse), &out_dir);
+    export_schema(&schema_for!(UserCredResponse), &out_dir);
+    export_schema(&schema_for!(EscrowResponse), &out_dir);
+    export_schema(&schema_for!(ClaimableResponse), &out_dir);
+    export_schema(&schema_for!(VestingResponse), &out_dir);
+    export_schema(&schema_for!(RevocationsResponse), &out_dir);
+    export_schema(&schema_for!(AllocationsResponse), &out_dir);
+    export_schema(&schema_for!(ListUsersResponse), &out_dir);
+    export_schema(&schema_for!(CredIdsResponse), &out_dir);
+    export_schema(&schema_for!(PendingOwnerResponse), &out_dir);
+    export_schema(&schema_for!(RoleHoldersResponse), &out_dir);
+    export_schema(&schema_for!(TreasuryResponse), &out_dir);
+    export_schema(&schema_for!(RewardTokensResponse), &out_dir);
+    export_schema(&schema_for!(ContractStatusResponse), &out_dir);
+    export_schema(&schema_for!(CapHeadroomResponse), &out_dir);
+    export_schema(&schema_for!(DistributionResponse), &out_dir);
+    export_schema(&schema_for!(DistributionRootResponse), &out_dir);
 }
@@ -1,8 +1,18 @@
-use cosmwasm_std::{to_binary, Api, Binary, CosmosMsg, Env, Extern, HandleResponse, InitResponse, log, Querier, StdError, StdResult, Storage, HumanAddr, Uint128};
+use cosmwasm_std::{coin, to_binary, to_vec, Api, Binary, CanonicalAddr, CosmosMsg, Env, Extern, HandleResponse, InitResponse, log, Querier, StdError, StdResult, Storage, HumanAddr, Uint128};
 
-use crate::msg::{ CredAllocatedResponse, CredRegisteredResponse, HandleMsg, InitMsg, QueryMsg, UserCredResponse, TotalAllocatedResponse};
-use crate::state::{config, config_read, user_cred, user_cred_read, State, UserCred, PolicyType, Allocation};
-use crate::tokens::mint;
+use crate::coin_helpers::assert_sent_sufficient_coin;
+use crate::crypto::{sha_256, verify_merkle_proof, verify_signature};
+use crate::msg::{ AllocationRecord, AllocationsResponse, BatchMode, ClaimableResponse, CredAllocatedResponse, CredIdsResponse, ListUsersResponse, UserInfo, CredRegisteredResponse, EscrowResponse, HandleAnswer, HandleMsg, IdentityAttestation, SignedAllocations, InitMsg, CapHeadroomResponse, ContractStatusResponse, DistributionResponse, DistributionRootResponse, PendingOwnerResponse, QueryMsg, QueryWithPermit, TreasuryResponse, Receipt, RewardTokensResponse, RoleHoldersResponse, UserCredResponse, TotalAllocatedResponse, RevocationRecord, RevocationsResponse, VestingResponse, VestingStatus};
+use crate::state::{allocation_totals, allocation_totals_read, cap_window, cap_window_read, Caps, WindowEntry, alias_creds, alias_creds_read, Uniqueness, allocation_index, allocation_index_read, allocation_position, allocation_position_read, allocations_indexed, allocations_indexed_read, merkle_roots, merkle_roots_read, MerkleRoot, root_claims, root_claims_read, revocations, revocations_indexed, revocations_indexed_read, revocations_read, Revocation, distributions, distributions_read, Distribution, address_cred, role_holders, role_holders_read, address_cred_read, allocations, allocations_read, config, config_read, deregistered_cred, deregistered_cred_read, registrations, registrations_read, escrow, escrow_read, prng_seed, revoked_permits, prng_seed_read, user_cred, user_cred_read, user_index, user_index_read, user_position, user_position_read, vesting, vesting_read, viewing_key, viewing_key_read, VestingSchedule, VestingTerms, ContractInfo, ContractStatus, RewardToken, State, TokenTotals, UserCred, PayoutMode, PolicyType, Allocation, ClaimMode, Role};
+use crate::permit::{self, Permission, Permit};
+use crate::snip20::{self, BLOCK_SIZE};
+use crate::tokens::{batch_payout, payout, NATIVE_DENOM};
+use crate::viewing_key::ViewingKey;
+
+pub const DEFAULT_PAGE_SIZE: u32 = 10;
+pub const MAX_PAGE_SIZE: u32 = 30;
+// index entries a filtered page looks at before returning what it found
+pub const MAX_SCAN_SIZE: u64 = 100;
 
 pub fn init<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
@@ -12,13 +22,42 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
     let state = State {
         total_cred: Uint128::zero(),
         total_users: 0,
+        users_indexed: 0,
+        total_escrowed: Uint128::zero(),
+        total_unclaimed: Uint128::zero(),
+        total_vesting: Uint128::zero(),
         owner: deps.api.canonical_address(&env.message.sender)?,
-        token_contract: msg.token_contract
+        pending_owner: None,
+        registrar_pubkey: None,
+        oracle_pubkey: None,
+        oracle_nonce: 0,
+        token_contract: msg.token_contract,
+        reward_tokens: vec![],
+        claim_mode: msg.claim_mode.unwrap_or(ClaimMode::Push),
+        payout_mode: msg.payout_mode.unwrap_or(PayoutMode::Mint),
+        treasury_balance: Uint128::zero(),
+        contract_address: env.contract.address,
+        status: ContractStatus::Normal,
+        caps: msg.caps.unwrap_or_default(),
+        balanced_vesting: msg.balanced_vesting,
+        policy_totals: vec![],
+        uniqueness: msg.uniqueness.unwrap_or_default(),
     };
 
+    // a treasury is funded by sending tokens, which the token only reports
+    // to contracts registered with it
+    let mut messages = vec![];
+    if state.payout_mode == PayoutMode::Transfer {
+        messages.push(snip20::HandleMsg::RegisterReceive {
+            code_hash: env.contract_code_hash,
+            padding: None,
+        }.to_cosmos_msg(BLOCK_SIZE, &state.token_contract)?);
+    }
+
     config(&mut deps.storage).save(&state)?;
+    prng_seed(&mut deps.storage).save(&Binary(sha_256(msg.prng_seed.as_slice()).to_vec()))?;
 
-    Ok(InitResponse::default())
+    Ok(InitResponse { messages, log: vec![] })
 }
 
 pub fn handle<S: Storage, A: Api, Q: Querier>(
@@ -26,547 +65,4624 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
     env: Env,
     msg: HandleMsg,
 ) -> StdResult<HandleResponse> {
+    let status = config_read(&deps.storage).load()?.status;
+    let enabled = match status {
+        ContractStatus::Normal => true,
+        ContractStatus::StopAllocations => !matches!(msg,
+            HandleMsg::Allocate { .. }
+            | HandleMsg::AllocateBatch { .. }
+            | HandleMsg::PublishDistributionRoot { .. }
+            | HandleMsg::ClaimFromRoot { .. }
+            | HandleMsg::SubmitSignedAllocations { .. }
+        ),
+        // a leaked registrar key could still repoint the payouts of existing creds
+        ContractStatus::StopRegistrations => !matches!(msg,
+            HandleMsg::RegisterUser { .. }
+            | HandleMsg::ClaimIdentity { .. }
+            | HandleMsg::UpdateUserAddress { .. }
+            | HandleMsg::DeregisterUser { .. }
+        ),
+        // the owner can still revoke leaked keys and roles, and hand over ownership
+        ContractStatus::StopAll => matches!(msg,
+            HandleMsg::SetStatus { .. }
+            | HandleMsg::SetOracleKey { .. }
+            | HandleMsg::SetRegistrarKey { .. }
+            | HandleMsg::GrantRole { .. }
+            | HandleMsg::RevokeRole { .. }
+            | HandleMsg::ProposeOwner { .. }
+            | HandleMsg::AcceptOwnership { .. }
+            | HandleMsg::CancelOwnershipProposal { .. }
+        ),
+    };
+    if !enabled {
+        return Err(StdError::generic_err("This action is disabled by the contract status"));
+    }
+
     match msg {
-        HandleMsg::Allocate { cred_id, allocation_id, amount,  policy_type } => try_allocate(
+        HandleMsg::Allocate { cred_id, allocation_id, amount,  policy_type, token } => try_allocate(
             deps,
             env,
             cred_id,
             allocation_id,
             amount,
-            policy_type
+            policy_type,
+            token,
         ),
         HandleMsg::RegisterUser { cred_id, scrt_address, alias } =>
             try_register_user(deps, env, cred_id, &scrt_address, alias),
+        HandleMsg::AllocateBatch { allocation_id, policy_type, receipts, mode, token } =>
+            try_allocate_batch(deps, env, allocation_id, policy_type, receipts, mode, token),
+        HandleMsg::Claim { amount, token, cred_id } => try_claim(deps, env, amount, token, cred_id),
+        HandleMsg::SetClaimMode { claim_mode } => try_set_claim_mode(deps, env, claim_mode),
+        HandleMsg::MigrateAllocations { cred_ids } => try_migrate_allocations(deps, env, cred_ids),
+        HandleMsg::ProposeOwner { address } => try_propose_owner(deps, env, &address),
+        HandleMsg::AcceptOwnership {} => try_accept_ownership(deps, env),
+        HandleMsg::CancelOwnershipProposal {} => try_cancel_ownership_proposal(deps, env),
+        HandleMsg::GrantRole { role, address } => try_grant_role(deps, env, role, &address),
+        HandleMsg::RevokeRole { role, address } => try_revoke_role(deps, env, role, &address),
+        HandleMsg::UpdateUserAddress { cred_id, scrt_address } =>
+            try_update_user_address(deps, env, cred_id, &scrt_address),
+        HandleMsg::DeregisterUser { cred_id } => try_deregister_user(deps, env, cred_id),
+        HandleMsg::SetRegistrarKey { pubkey } => try_set_registrar_key(deps, env, pubkey),
+        HandleMsg::SetUniqueness { uniqueness } => try_set_uniqueness(deps, env, uniqueness),
+        HandleMsg::SetOracleKey { pubkey } => try_set_oracle_key(deps, env, pubkey),
+        HandleMsg::SubmitSignedAllocations { payload, signature } =>
+            try_submit_signed_allocations(deps, env, payload, signature),
+        HandleMsg::ClaimIdentity { cred_id, alias, expiry, signature } =>
+            try_claim_identity(deps, env, cred_id, alias, expiry, signature),
+        HandleMsg::CreateViewingKey { entropy, .. } => try_create_viewing_key(deps, env, entropy),
+        HandleMsg::SetViewingKey { key, .. } => try_set_viewing_key(deps, env, key),
+        HandleMsg::RevokePermit { permit_name, .. } => try_revoke_permit(deps, env, permit_name),
+        HandleMsg::AddRewardToken { token } => try_add_reward_token(deps, env, token),
+        HandleMsg::SetStatus { status } => try_set_status(deps, env, status),
+        HandleMsg::SetCaps { caps, token } => try_set_caps(deps, env, caps, token),
+        HandleMsg::SetBalancedVesting { terms } => try_set_balanced_vesting(deps, env, terms),
+        HandleMsg::RevokeAllocation { cred_id, allocation_id, reason } =>
+            try_revoke_allocation(deps, env, cred_id, allocation_id, reason),
+        HandleMsg::PublishDistributionRoot { distribution_id, merkle_root, total, token } =>
+            try_publish_distribution_root(deps, env, distribution_id, merkle_root, total, token),
+        HandleMsg::ClaimFromRoot { distribution_id, amount, proof, cred_id } =>
+            try_claim_from_root(deps, env, distribution_id, amount, proof, cred_id),
+        HandleMsg::CreateDistribution { id, policy, budget, timestamp, token } =>
+            try_create_distribution(deps, env, id, policy, budget, timestamp, token),
+        HandleMsg::FinalizeDistribution { id } => try_finalize_distribution(deps, env, id),
+        HandleMsg::Fund { amount } => try_fund(deps, env, amount),
+        HandleMsg::Receive { from, amount, .. } => try_receive(deps, env, from, amount),
     }
 }
 
-pub fn try_allocate<S: Storage, A: Api, Q: Querier>(
-    deps: &mut Extern<S, A, Q>,
-    env: Env,
-    cred_id: String,
-    allocation_id: String,
-    amount: Uint128,
-    policy_type: PolicyType,
-) -> StdResult<HandleResponse> {
-
-    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+fn has_role<S: Storage>(storage: &S, state: &State, role: Role, address: &CanonicalAddr) -> StdResult<bool> {
+    if address == &state.owner {
+        return Ok(true);
+    }
+    if role == Role::Owner {
+        return Ok(false);
+    }
 
-    let allocation = Allocation {
-        policy: policy_type,
-        amount,
-        allocation_id
-    };
+    let holders = role_holders_read(storage).may_load(role.as_str().as_bytes())?.unwrap_or_default();
+    Ok(holders.contains(address))
+}
 
-    let mut state = config(&mut deps.storage).load()?;
-    // only owner
-    if sender_address_raw != state.owner {
+fn assert_role<S: Storage>(storage: &S, state: &State, role: Role, address: &CanonicalAddr) -> StdResult<()> {
+    if !has_role(storage, state, role, address)? {
         return Err(StdError::Unauthorized { backtrace: None });
     }
+    Ok(())
+}
 
-    let key = &cred_id.as_bytes();
-    if let Some(mut cred) = user_cred(&mut deps.storage).may_load(key)? {
+// appends cred_id to the registration index, unless it is listed already
+fn index_user<S: Storage>(storage: &mut S, state: &mut State, cred_id: &str) -> StdResult<()> {
+    if user_position_read(storage).may_load(cred_id.as_bytes())?.is_some() {
+        return Ok(());
+    }
+    let position = state.users_indexed;
+    user_index(storage).save(&position.to_be_bytes(), &cred_id.to_string())?;
+    user_position(storage).save(cred_id.as_bytes(), &position)?;
+    state.users_indexed += 1;
+    Ok(())
+}
 
-        if cred.allocations.contains(&allocation) {
-            return Err(StdError::GenericErr {
-                msg: "Already allocated".to_string(), backtrace: None })
-        }
+// saves an allocation of cred_id and appends it to the cred's allocation index
+fn save_allocation<S: Storage>(storage: &mut S, cred_id: &str, allocation: &Allocation) -> StdResult<()> {
+    let id = allocation.allocation_id.as_bytes();
+    allocations(storage, cred_id).save(id, allocation)?;
+    if allocation_position_read(storage, cred_id).may_load(id)?.is_some() {
+        return Ok(());
+    }
 
-        state.total_cred += amount;
-        cred.total_allocated += amount;
-        cred.allocations.push(allocation);
+    let position = allocations_indexed_read(storage).may_load(cred_id.as_bytes())?.unwrap_or(0);
+    allocation_index(storage, cred_id).save(&position.to_be_bytes(), &allocation.allocation_id)?;
+    allocation_position(storage, cred_id).save(id, &position)?;
+    allocations_indexed(storage).save(cred_id.as_bytes(), &(position + 1))
+}
 
-        user_cred(&mut deps.storage).save(key, &cred)?;
-        config(&mut deps.storage).save(&state)?;
-        let scrt_addy = deps.api.human_address(&cred.scrt_address)?;
+// refuses an address registered to another cred_id while addresses are unique
+fn assert_address_available<S: Storage>(
+    storage: &S,
+    state: &State,
+    address: &CanonicalAddr,
+    cred_id: &str,
+) -> StdResult<()> {
+    if !state.uniqueness.addresses {
+        return Ok(());
+    }
+    let cred_ids = address_cred_read(storage).may_load(address.as_slice())?.unwrap_or_default();
+    if cred_ids.iter().any(|registered| registered != cred_id) {
+        return Err(StdError::generic_err("Address already registered to another cred"));
+    }
+    Ok(())
+}
 
-        let mut messages: Vec<CosmosMsg> = vec![];
+fn add_address<S: Storage>(storage: &mut S, address: &CanonicalAddr, cred_id: &str) -> StdResult<()> {
+    let mut cred_ids = address_cred_read(storage).may_load(address.as_slice())?.unwrap_or_default();
+    if !cred_ids.iter().any(|registered| registered == cred_id) {
+        cred_ids.push(cred_id.to_string());
+    }
+    address_cred(storage).save(address.as_slice(), &cred_ids)
+}
 
-        messages.push(mint(
-            &deps.storage,
-            amount,
-            scrt_addy,
-        )?);
-        let res = HandleResponse {
-            messages,
-            log: vec![
-                log("action", "allocate-cred"),
-                log("account", env.message.sender.as_str()),
-                log("amount", &amount.to_string()),
-            ],
-            data: None,
-        };
-        Ok(res)
-    } else {
-        return Err(StdError::GenericErr {
-            msg: "User not registered".to_string(), backtrace: None })
+// drops the binding of address to cred_id, keeping those of other creds
+fn remove_address<S: Storage>(storage: &mut S, address: &CanonicalAddr, cred_id: &str) -> StdResult<()> {
+    let mut cred_ids = address_cred_read(storage).may_load(address.as_slice())?.unwrap_or_default();
+    cred_ids.retain(|registered| registered != cred_id);
+    if cred_ids.is_empty() {
+        address_cred(storage).remove(address.as_slice());
+        return Ok(());
     }
+    address_cred(storage).save(address.as_slice(), &cred_ids)
 }
 
-pub fn try_register_user<S: Storage, A: Api, Q: Querier>(
-    deps: &mut Extern<S, A, Q>,
-    env: Env,
-    cred_id: String,
-    scrt_address: &HumanAddr,
-    alias: Option<String>,
-) -> StdResult<HandleResponse> {
-    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
-    let mut state = config(&mut deps.storage).load()?;
-    if sender_address_raw != state.owner {
-        return Err(StdError::Unauthorized { backtrace: None });
+// the cred registered to address that the sender acts for, cred_id must pick
+// one when several are registered to it
+fn sender_cred_id<S: Storage>(storage: &S, address: &CanonicalAddr, cred_id: Option<String>) -> StdResult<String> {
+    let cred_ids = address_cred_read(storage).may_load(address.as_slice())?.unwrap_or_default();
+    match cred_id {
+        Some(cred_id) if cred_ids.contains(&cred_id) => Ok(cred_id),
+        Some(cred_id) => Err(StdError::generic_err(format!("Address is not registered to cred {}", cred_id))),
+        None => match cred_ids.as_slice() {
+            [] => Err(StdError::generic_err("User not registered")),
+            [cred_id] => Ok(cred_id.clone()),
+            _ => Err(StdError::generic_err("Address is registered to several creds, pick one with cred_id")),
+        },
     }
+}
 
-    // user must not exist
-    let key = cred_id.as_bytes();
-    let registered = match user_cred_read(&deps.storage).may_load(key)? {
-        Some(_) => Some(true),
-        None => Some(false),
-    }.unwrap();
-    if registered {
-        return Err(StdError::GenericErr {
-            msg: "User already exists".to_string(), backtrace: None })
+fn add_alias<S: Storage>(storage: &mut S, unique: bool, alias: &str, cred_id: &str) -> StdResult<()> {
+    let mut cred_ids = alias_creds_read(storage).may_load(alias.as_bytes())?.unwrap_or_default();
+    if unique && cred_ids.iter().any(|registered| registered != cred_id) {
+        return Err(StdError::generic_err("Alias already registered to another cred"));
+    }
+    if !cred_ids.iter().any(|registered| registered == cred_id) {
+        cred_ids.push(cred_id.to_string());
     }
+    alias_creds(storage).save(alias.as_bytes(), &cred_ids)
+}
 
-    let scrt_address_raw = deps.api.canonical_address(scrt_address)?;
+fn remove_alias<S: Storage>(storage: &mut S, alias: &str, cred_id: &str) -> StdResult<()> {
+    let mut cred_ids = alias_creds_read(storage).may_load(alias.as_bytes())?.unwrap_or_default();
+    cred_ids.retain(|registered| registered != cred_id);
+    if cred_ids.is_empty() {
+        alias_creds(storage).remove(alias.as_bytes());
+        return Ok(());
+    }
+    alias_creds(storage).save(alias.as_bytes(), &cred_ids)
+}
 
-    let cred = &UserCred{
-        cred_id: cred_id.to_string(),
-        scrt_address: scrt_address_raw,
-        alias,
-        total_allocated: Uint128::zero(),
-        allocations: vec![]
+// a registered cred to change, records still holding allocations inline must
+// be migrated first, saving them would drop those allocations
+fn load_cred<S: Storage>(storage: &S, key: &[u8]) -> StdResult<Option<UserCred>> {
+    let cred = user_cred_read(storage).may_load(key)?;
+    if let Some(cred) = &cred {
+        if !cred.allocations.is_empty() {
+            return Err(StdError::generic_err(format!("Allocations of {} must be migrated first", cred.cred_id)));
+        }
+    }
+    Ok(cred)
+}
+
+fn is_allocated<S: Storage>(storage: &S, cred_id: &str, allocation_id: &str) -> StdResult<bool> {
+    Ok(allocations_read(storage, cred_id).may_load(allocation_id.as_bytes())?.is_some())
+}
+
+// credits an allocation of token to a registered user, returning the amount
+// to pay them now, which is zero when the user claims it later instead
+fn credit_user(state: &mut State, cred: &mut UserCred, token: &Option<HumanAddr>, amount: Uint128) -> StdResult<Uint128> {
+    let mut balance = cred.balance(token);
+    balance.total_allocated += amount;
+    let amount = balance.offset_debt(amount);
+
+    let payable = match state.claim_mode {
+        ClaimMode::Push => amount,
+        ClaimMode::Claim => {
+            balance.claimable += amount;
+            state.update_totals(token, |totals| {
+                totals.total_unclaimed += amount;
+                Ok(())
+            })?;
+            Uint128::zero()
+        }
     };
 
-    user_cred(&mut deps.storage).save(key, &cred)?;
+    cred.set_balance(balance);
+    Ok(payable)
+}
 
-    state.total_users = state.total_users + 1;
-    config(&mut deps.storage).save(&state)?;
+// holds an allocation of token for a cred_id until it registers an address
+fn escrow_allocation<S: Storage>(
+    storage: &mut S,
+    state: &mut State,
+    cred_id: &str,
+    token: &Option<HumanAddr>,
+    amount: Uint128,
+) -> StdResult<()> {
+    let key = cred_id.as_bytes();
+    let mut pending = escrow_read(storage).may_load(key)?.unwrap_or_default();
+    pending.add(token, amount);
+    escrow(storage).save(key, &pending)?;
 
-    Ok(HandleResponse::default())
+    state.update_totals(token, |totals| {
+        totals.total_escrowed += amount;
+        Ok(())
+    })
 }
 
-pub fn query<S: Storage, A: Api, Q: Querier>(
-    deps: &Extern<S, A, Q>,
-    msg: QueryMsg,
-) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::Config {} => to_binary(&config_read(&deps.storage).load()?),
-        QueryMsg::GetTotalAllocated { cred_id } => to_binary(
-            &query_total_allocated(deps, cred_id)?),
-        QueryMsg::IsCredRegistered { cred_id } => to_binary(&query_user_registered(deps, cred_id)?),
-        QueryMsg::GetUserCred { cred_id } => to_binary(&query_user_cred(deps, cred_id)?),
-        QueryMsg::IsAllocated { cred_id, allocation_id } => to_binary(&query_allocated(deps, cred_id, allocation_id)?),
+// the terms an allocation under policy vests on, None when it is paid right away
+fn vesting_terms(state: &State, policy: &PolicyType) -> Option<VestingTerms> {
+    match policy {
+        PolicyType::Immediate | PolicyType::Recent { .. } | PolicyType::Special { .. } => None,
+        PolicyType::Balanced => state.balanced_vesting.clone(),
+        PolicyType::Vesting(terms) => Some(terms.clone()),
     }
 }
 
-fn query_user_registered<S: Storage, A: Api, Q: Querier>(
-    deps: &Extern<S, A, Q>, id: String) -> StdResult<CredRegisteredResponse> {
-    let key = &id.as_bytes();
-    let registered = match user_cred_read(&deps.storage).may_load(key)? {
-        Some(_) => Some(true),
-        None => Some(false),
-    }.unwrap();
+// vests an allocation to cred_id from start, whether or not it registered yet
+fn start_vesting<S: Storage>(
+    storage: &mut S,
+    state: &mut State,
+    cred_id: &str,
+    allocation: &Allocation,
+    terms: VestingTerms,
+    start: u64,
+) -> StdResult<()> {
+    let key = cred_id.as_bytes();
+    let mut schedules = vesting_read(storage).may_load(key)?.unwrap_or_default();
+    schedules.push(VestingSchedule {
+        allocation_id: allocation.allocation_id.clone(),
+        token: allocation.token.clone(),
+        amount: allocation.amount,
+        start,
+        terms,
+        released: Uint128::zero(),
+    });
+    vesting(storage).save(key, &schedules)?;
 
-    Ok(CredRegisteredResponse { registered })
+    state.update_totals(&allocation.token, |totals| {
+        totals.total_vesting += allocation.amount;
+        Ok(())
+    })
 }
 
-fn query_allocated<S: Storage, A: Api, Q: Querier>(
-    deps: &Extern<S, A, Q>, id: String, allocation_id: String) -> StdResult<CredAllocatedResponse> {
-    let key = &id.as_bytes();
-    let cred = match user_cred_read(&deps.storage).may_load(key)? {
-        Some(cred) => Some(cred),
-        None => return Err(StdError::GenericErr { msg: "User does not exist".to_string(), backtrace: None }),
-    }.unwrap();
+// moves what vested by time in the cred's token schedules to its claimable
+// balance, vesting allocations count towards total_allocated once released
+fn release_vested<S: Storage>(
+    storage: &mut S,
+    state: &mut State,
+    cred: &mut UserCred,
+    token: &Option<HumanAddr>,
+    time: u64,
+) -> StdResult<Uint128> {
+    let key = cred.cred_id.as_bytes();
+    let mut schedules = vesting_read(storage).may_load(key)?.unwrap_or_default();
 
-    let allocation = Allocation {
-        policy: PolicyType::Balanced,
-        amount: Uint128::zero(),
-        allocation_id
-    };
+    let mut released = Uint128::zero();
+    for schedule in schedules.iter_mut().filter(|schedule| &schedule.token == token) {
+        let amount = (schedule.vested(time) - schedule.released)?;
+        schedule.released += amount;
+        released += amount;
+    }
+    if released.is_zero() {
+        return Ok(released);
+    }
+    // fully released schedules have nothing left to do
+    schedules.retain(|schedule| schedule.released < schedule.amount);
+    if schedules.is_empty() {
+        vesting(storage).remove(key);
+    } else {
+        vesting(storage).save(key, &schedules)?;
+    }
 
-    Ok(CredAllocatedResponse { allocated: cred.allocations.contains(&allocation) })
+    let mut balance = cred.balance(token);
+    balance.total_allocated += released;
+    let due = balance.offset_debt(released);
+    balance.claimable += due;
+    cred.set_balance(balance);
+    state.update_totals(token, |totals| {
+        totals.total_vesting = (totals.total_vesting - released)?;
+        totals.total_unclaimed += due;
+        Ok(())
+    })?;
+    Ok(released)
 }
 
-fn query_user_cred<S: Storage, A: Api, Q: Querier>(
-    deps: &Extern<S, A, Q>, id: String) -> StdResult<UserCredResponse> {
-    let key = &id.as_bytes();
-    let cred = match user_cred_read(&deps.storage).may_load(key)? {
-        Some(cred) => Some(cred),
-        None => return Err(StdError::GenericErr { msg: "User does not exist".to_string(), backtrace: None }),
-    }.unwrap();
+// outside PayoutMode::Mint the treasury has to cover a new allocation on
+// top of what it already owes to escrowed, claimable and vesting balances
+fn assert_treasury_covers(state: &State, token: &Option<HumanAddr>, amount: Uint128) -> StdResult<()> {
+    if state.payout_mode == PayoutMode::Mint {
+        return Ok(());
+    }
 
-    Ok(UserCredResponse { scrt_address: cred.scrt_address, total_allocated: cred.total_allocated })
+    let totals = state.totals(token)?;
+    let owed = totals.total_escrowed + totals.total_unclaimed + totals.total_vesting;
+    let available = (totals.treasury_balance - owed).unwrap_or_else(|_| Uint128::zero());
+    if amount > available {
+        return Err(StdError::generic_err(format!(
+            "Insufficient treasury balance: {} available", available
+        )));
+    }
+    Ok(())
 }
 
-fn query_total_allocated<S: Storage, A: Api, Q: Querier>(
-    deps: &Extern<S, A, Q>, id: String) -> StdResult<TotalAllocatedResponse> {
-
-    let key = &id.as_bytes();
-    let cred = match user_cred_read(&deps.storage).may_load(key)? {
-        Some(cred) => Some(cred),
-        None => return Err(StdError::generic_err("User is not registered")),
-    }.unwrap();
-
-    Ok(TotalAllocatedResponse { total_allocated: cred.total_allocated })
+fn cap_exceeded(cap: &str, max: Uint128) -> StdError {
+    StdError::generic_err(format!("Cap exceeded: at most {} {}", max, cap))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, MockApi, MockQuerier, MockStorage};
-    use cosmwasm_std::{coins, from_binary, StdError};
-    use crate::state::{ContractInfo};
-    pub const TOKEN_HASH: &str = "foocoinhash";
-    
-    const TEST_CREATOR: &str = "creator";
-
-    #[test]
-    fn proper_initialization() {
-        let mut deps = mock_dependencies(20, &[]);
-        mock_init(&mut deps);
+// total of the entries in token still inside the window ending at time
+fn window_total(entries: &[WindowEntry], caps: &Caps, time: u64, token: &Option<HumanAddr>) -> Uint128 {
+    let mut total = Uint128::zero();
+    for entry in entries.iter().filter(|entry| &entry.token == token && entry.time.saturating_add(caps.window_seconds) > time) {
+        total += entry.amount;
+    }
+    total
+}
 
-        // it worked, let's query the state
-        let res = query(&deps, QueryMsg::Config {}).unwrap();
-        let state: State = from_binary(&res).unwrap();
-        let token_contract = ContractInfo {
-            code_hash: TOKEN_HASH.to_string(),
-            address: HumanAddr(TOKEN_HASH.to_string()),
-        };
+// checks new shares of allocation_id in token against the token's caps and
+// counts them towards the allocation_id and window totals
+fn apply_caps<S: Storage>(
+    storage: &mut S,
+    state: &State,
+    time: u64,
+    allocation_id: &str,
+    token: &Option<HumanAddr>,
+    amounts: &[Uint128],
+) -> StdResult<()> {
+    let caps = state.caps(token)?;
 
-        assert_eq!(
-            state,
-            State {
-                total_cred: Uint128::zero(),
-                total_users: 0,
-                token_contract,
-                owner: deps
-                    .api
-                    .canonical_address(&HumanAddr::from(TEST_CREATOR))
-                    .unwrap(),
+    let mut total = Uint128::zero();
+    for amount in amounts {
+        if let Some(max) = caps.max_per_user {
+            if *amount > max {
+                return Err(cap_exceeded("per user per allocation", max));
             }
-        );
+        }
+        total += *amount;
     }
 
-    fn mock_init(mut deps: &mut Extern<MockStorage, MockApi, MockQuerier>) {
-        let token_contract = ContractInfo {
-            code_hash: TOKEN_HASH.to_string(),
-            address: HumanAddr(TOKEN_HASH.to_string()),
-        };
-
-        let msg = InitMsg { token_contract };
-        let env = mock_env(TEST_CREATOR, &coins(1000, "hush money"));
+    let allocated = allocation_totals_read(storage, token)
+        .may_load(allocation_id.as_bytes())?
+        .unwrap_or_else(Uint128::zero) + total;
+    if let Some(max) = caps.max_per_allocation {
+        if allocated > max {
+            return Err(cap_exceeded("per allocation", max));
+        }
+    }
+    allocation_totals(storage, token).save(allocation_id.as_bytes(), &allocated)?;
 
-        let _res = init(&mut deps, env, msg).expect("contract successfully handles InitMsg");
+    if let Some(max) = caps.max_per_window {
+        let mut entries = cap_window_read(storage).may_load()?.unwrap_or_default();
+        entries.retain(|entry| &entry.token != token || entry.time.saturating_add(caps.window_seconds) > time);
+        if window_total(&entries, caps, time, token) + total > max {
+            return Err(cap_exceeded("per window", max));
+        }
+        entries.push(WindowEntry { time, amount: total, token: token.clone() });
+        cap_window(storage).save(&entries)?;
     }
+    Ok(())
+}
 
-    fn assert_registered(
-        deps: &mut Extern<MockStorage, MockApi, MockQuerier>,
-        cred_id: &str,
-        expected: bool,
-    ) {
-        let res = query(
-            &deps,
-            QueryMsg::IsCredRegistered {
-                cred_id: cred_id.to_string(),
-            },
-        ).unwrap();
+// counts new shares of allocation_id against the distribution of that id,
+// returning whether there is one, allocations without one are left alone
+fn apply_distribution<S: Storage>(
+    storage: &mut S,
+    allocation_id: &str,
+    policy: &PolicyType,
+    token: &Option<HumanAddr>,
+    amounts: &[Uint128],
+) -> StdResult<bool> {
+    let mut distribution = match distributions_read(storage).may_load(allocation_id.as_bytes())? {
+        Some(distribution) => distribution,
+        None => return Ok(false),
+    };
 
-        let value: CredRegisteredResponse = from_binary(&res).unwrap();
-        assert_eq!(expected, value.registered);
+    if distribution.finalized {
+        return Err(StdError::generic_err("Distribution is finalized"));
     }
-
-    fn assert_config_state(deps: &mut Extern<MockStorage, MockApi, MockQuerier>, expected: State) {
-        let res = query(&deps, QueryMsg::Config {}).unwrap();
-        let value: State = from_binary(&res).unwrap();
-        assert_eq!(value, expected);
+    if &distribution.policy != policy {
+        return Err(StdError::generic_err("Policy does not match distribution"));
+    }
+    if &distribution.token != token {
+        return Err(StdError::generic_err("Token does not match distribution"));
     }
 
-    fn assert_cred_balance(deps: &mut Extern<MockStorage, MockApi, MockQuerier>, expected: UserCred) {
-        let res = query(&deps, QueryMsg::GetUserCred { cred_id: expected.cred_id }).unwrap();
-        let value: UserCredResponse = from_binary(&res).unwrap();
-        assert_eq!(value.total_allocated, expected.total_allocated);
+    for amount in amounts {
+        distribution.allocated += *amount;
+    }
+    if distribution.allocated > distribution.budget {
+        let remaining = (distribution.budget - (distribution.allocated - total_of(amounts))?)?;
+        return Err(StdError::generic_err(format!(
+            "Distribution budget exceeded: {} remaining", remaining
+        )));
     }
+    distribution.recipients += amounts.len() as u64;
 
-    fn assert_cred_allocated(deps: &mut Extern<MockStorage, MockApi, MockQuerier>, cred_id: String, allocation_id: String, expected: bool) {
-        let res = query(&deps, QueryMsg::IsAllocated { cred_id, allocation_id }).unwrap();
-        let value: CredAllocatedResponse = from_binary(&res).unwrap();
-        assert_eq!(value.allocated, expected);
+    distributions(storage).save(allocation_id.as_bytes(), &distribution)?;
+    Ok(true)
+}
+
+fn total_of(amounts: &[Uint128]) -> Uint128 {
+    let mut total = Uint128::zero();
+    for amount in amounts {
+        total += *amount;
     }
+    total
+}
 
-    #[test]
-    fn register_cred_and_query_works() {
-        let token_contract = ContractInfo {
-            code_hash: TOKEN_HASH.to_string(),
-            address: HumanAddr(TOKEN_HASH.to_string()),
-        };
-        let mut deps = mock_dependencies(20, &[]);
-        let env = mock_env(TEST_CREATOR, &coins(1000, "hush money"));
-        mock_init(&mut deps);
+pub fn try_allocate<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    cred_id: String,
+    allocation_id: String,
+    amount: Uint128,
+    policy_type: PolicyType,
+    token: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
 
-        let cred_id = "cred1".to_string();
-        let msg = HandleMsg::RegisterUser {
-            cred_id,
-            scrt_address: HumanAddr("secret007".to_string()),
-            alias: Some("secret007".to_string()),
-        };
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
 
-        let _res = handle(&mut deps, env, msg).expect("contract successfully registers cred");
-        assert_registered(&mut deps, "cred1", true);
+    let mut state = config(&mut deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Allocator, &sender_address_raw)?;
+    let token = state.token_key(token)?;
 
-        let owner_raw = deps
-            .api
-            .canonical_address(&HumanAddr::from(TEST_CREATOR))
-            .unwrap();
-        assert_config_state(&mut deps, State{
-            total_cred: Uint128::zero(),
-            total_users: 1,
-            token_contract,
-            owner: owner_raw,
-        });
+    let mut allocation = Allocation {
+        policy: policy_type,
+        amount,
+        allocation_id,
+        token: token.clone(),
+        in_distribution: false,
+    };
 
-        assert_cred_balance(&mut deps, UserCred {
-            cred_id: "cred1".to_string(),
-            scrt_address: Default::default(),
-            total_allocated: Uint128::zero(),
-            allocations: vec![],
-            alias: None,
+    if is_allocated(&deps.storage, &cred_id, &allocation.allocation_id)? {
+        return Err(StdError::GenericErr {
+            msg: "Already allocated".to_string(), backtrace: None })
+    }
+    assert_treasury_covers(&state, &token, amount)?;
+    apply_caps(&mut deps.storage, &state, env.block.time, &allocation.allocation_id, &token, &[amount])?;
+    allocation.in_distribution = apply_distribution(&mut deps.storage, &allocation.allocation_id, &allocation.policy, &token, &[amount])?;
+    save_allocation(&mut deps.storage, &cred_id, &allocation)?;
+
+    state.update_totals(&token, |totals| {
+        totals.total_cred += amount;
+        Ok(())
+    })?;
+    state.add_policy_total(&token, &allocation.policy, amount)?;
+
+    if let Some(terms) = vesting_terms(&state, &allocation.policy) {
+        start_vesting(&mut deps.storage, &mut state, &cred_id, &allocation, terms, env.block.time)?;
+        config(&mut deps.storage).save(&state)?;
+
+        return Ok(HandleResponse {
+            messages: vec![],
+            log: vec![
+                log("action", "vest-cred"),
+                log("account", env.message.sender.as_str()),
+                log("cred_id", &cred_id),
+                log("amount", amount.to_string()),
+            ],
+            data: None,
         });
     }
 
-    #[test]
-    fn allocate_cred_works() {
-        let mut deps = mock_dependencies(20, &[]);
-        mock_init(&mut deps);
-        let env = mock_env(TEST_CREATOR, &coins(100u128, "foocoin"));
+    let key = &cred_id.as_bytes();
+    if let Some(mut cred) = load_cred(&deps.storage, key)? {
 
-        let cred_id = "cred1".to_string();
-        let msg = HandleMsg::RegisterUser {
-            cred_id,
-            scrt_address: HumanAddr("secret007".to_string()),
-            alias: None,
-        };
+        let payable = credit_user(&mut state, &mut cred, &token, amount)?;
+        let scrt_addy = deps.api.human_address(&cred.scrt_address)?;
 
-        let _res = handle(&mut deps, env.clone(), msg).expect("contract successfully registers cred");
-        assert_registered(&mut deps, "cred1", true);
+        let mut messages: Vec<CosmosMsg> = vec![];
+
+        if !payable.is_zero() {
+            messages.push(payout(
+                &mut state,
+                &token,
+                payable,
+                scrt_addy,
+                Some(allocation.allocation_id.clone()),
+            )?);
+        }
+
+        user_cred(&mut deps.storage).save(key, &cred)?;
+        config(&mut deps.storage).save(&state)?;
+        let res = HandleResponse {
+            messages,
+            log: vec![
+                log("action", "allocate-cred"),
+                log("account", env.message.sender.as_str()),
+                log("amount", amount.to_string()),
+            ],
+            data: None,
+        };
+        Ok(res)
+    } else {
+        // hold the allocation until the cred_id registers an address
+        escrow_allocation(&mut deps.storage, &mut state, &cred_id, &token, amount)?;
+        config(&mut deps.storage).save(&state)?;
+
+        let res = HandleResponse {
+            messages: vec![],
+            log: vec![
+                log("action", "escrow-cred"),
+                log("account", env.message.sender.as_str()),
+                log("cred_id", &cred_id),
+                log("amount", amount.to_string()),
+            ],
+            data: None,
+        };
+        Ok(res)
+    }
+}
+
+pub fn try_allocate_batch<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    allocation_id: String,
+    policy_type: PolicyType,
+    receipts: Vec<Receipt>,
+    mode: BatchMode,
+    token: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+
+    let state = config(&mut deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Allocator, &sender_address_raw)?;
+
+    allocate_batch(deps, env, state, allocation_id, policy_type, receipts, mode, token)
+}
+
+// allocates a batch signed by the oracle key, sent by anyone who pays the gas
+pub fn try_submit_signed_allocations<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    payload: SignedAllocations,
+    signature: Binary,
+) -> StdResult<HandleResponse> {
+    let mut state = config(&mut deps.storage).load()?;
+    let oracle_pubkey = match &state.oracle_pubkey {
+        Some(pubkey) => pubkey.clone(),
+        None => return Err(StdError::generic_err("Signed allocations are not enabled")),
+    };
+
+    let message = to_vec(&payload)?;
+    if !verify_signature(&message, signature.as_slice(), oracle_pubkey.as_slice())? {
+        return Err(StdError::generic_err("Invalid oracle signature"));
+    }
+    // bound to this contract, and every payload is only accepted once
+    if payload.contract_address != state.contract_address {
+        return Err(StdError::generic_err("Payload is for another contract"));
+    }
+    if payload.nonce <= state.oracle_nonce {
+        return Err(StdError::generic_err(format!(
+            "Nonce must be greater than {}", state.oracle_nonce
+        )));
+    }
+    state.oracle_nonce = payload.nonce;
+
+    allocate_batch(
+        deps,
+        env,
+        state,
+        payload.allocation_id,
+        payload.policy_type,
+        payload.receipts,
+        payload.mode,
+        payload.token,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn allocate_batch<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    mut state: State,
+    allocation_id: String,
+    policy_type: PolicyType,
+    receipts: Vec<Receipt>,
+    mode: BatchMode,
+    token: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let token = state.token_key(token)?;
+
+    if receipts.is_empty() {
+        return Err(StdError::generic_err("No receipts to allocate"));
+    }
+
+    // validate every receipt before touching storage, so the batch is
+    // applied for all receipts or for none of them
+    let mut accepted: Vec<Receipt> = vec![];
+    let mut skipped = 0u64;
+
+    for (i, receipt) in receipts.iter().enumerate() {
+        // a cred listed twice is an oracle error, skipping either receipt would
+        // silently drop an amount
+        if receipts[..i].iter().any(|r| r.cred_id == receipt.cred_id) {
+            return Err(StdError::generic_err(format!("Duplicate receipt: {}", receipt.cred_id)));
+        }
+    }
+    for receipt in receipts {
+        if is_allocated(&deps.storage, &receipt.cred_id, &allocation_id)? {
+            match mode {
+                BatchMode::Skip => {
+                    skipped += 1;
+                    continue;
+                }
+                BatchMode::Reject => return Err(StdError::generic_err(
+                    format!("Already allocated: {}", receipt.cred_id))),
+            }
+        }
+        accepted.push(receipt);
+    }
+
+    let mut batch_total = Uint128::zero();
+    for receipt in accepted.iter() {
+        batch_total += receipt.amount;
+    }
+    assert_treasury_covers(&state, &token, batch_total)?;
+    let amounts: Vec<Uint128> = accepted.iter().map(|receipt| receipt.amount).collect();
+    apply_caps(&mut deps.storage, &state, env.block.time, &allocation_id, &token, &amounts)?;
+    let in_distribution = apply_distribution(&mut deps.storage, &allocation_id, &policy_type, &token, &amounts)?;
+
+    let mut actions: Vec<(HumanAddr, Uint128)> = vec![];
+    let mut allocated = 0u64;
+    let mut escrowed = 0u64;
+    let mut vested = 0u64;
+    let mut total = Uint128::zero();
+    let terms = vesting_terms(&state, &policy_type);
+
+    for receipt in accepted {
+        let allocation = Allocation {
+            policy: policy_type.clone(),
+            amount: receipt.amount,
+            allocation_id: allocation_id.clone(),
+            token: token.clone(),
+            in_distribution,
+        };
+        save_allocation(&mut deps.storage, &receipt.cred_id, &allocation)?;
+        total += receipt.amount;
+
+        if let Some(terms) = &terms {
+            start_vesting(&mut deps.storage, &mut state, &receipt.cred_id, &allocation, terms.clone(), env.block.time)?;
+            vested += 1;
+            continue;
+        }
+
+        let key = receipt.cred_id.as_bytes();
+        if let Some(mut cred) = load_cred(&deps.storage, key)? {
+            let payable = credit_user(&mut state, &mut cred, &token, receipt.amount)?;
+            user_cred(&mut deps.storage).save(key, &cred)?;
+            if !payable.is_zero() {
+                actions.push((deps.api.human_address(&cred.scrt_address)?, payable));
+            }
+            allocated += 1;
+        } else {
+            escrow_allocation(&mut deps.storage, &mut state, &receipt.cred_id, &token, receipt.amount)?;
+            escrowed += 1;
+        }
+    }
+    state.update_totals(&token, |totals| {
+        totals.total_cred += total;
+        Ok(())
+    })?;
+    state.add_policy_total(&token, &policy_type, total)?;
+
+    let mut messages: Vec<CosmosMsg> = vec![];
+    if !actions.is_empty() {
+        messages.extend(batch_payout(&mut state, &token, actions, Some(allocation_id.clone()))?);
+    }
+
+    config(&mut deps.storage).save(&state)?;
+
+    let res = HandleResponse {
+        messages,
+        log: vec![
+            log("action", "allocate-batch"),
+            log("account", env.message.sender.as_str()),
+            log("allocation_id", &allocation_id),
+            log("allocated", allocated.to_string()),
+            log("escrowed", escrowed.to_string()),
+            log("vesting", vested.to_string()),
+            log("skipped", skipped.to_string()),
+            log("amount", total.to_string()),
+        ],
+        data: None,
+    };
+    Ok(res)
+}
+
+pub fn try_register_user<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    cred_id: String,
+    scrt_address: &HumanAddr,
+    alias: Option<String>,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let state = config(&mut deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Registrar, &sender_address_raw)?;
+
+    register_user(deps, state, cred_id, scrt_address, alias)
+}
+
+pub fn try_claim_identity<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    cred_id: String,
+    alias: Option<String>,
+    expiry: u64,
+    signature: Binary,
+) -> StdResult<HandleResponse> {
+    let state = config(&mut deps.storage).load()?;
+    let registrar_pubkey = match &state.registrar_pubkey {
+        Some(pubkey) => pubkey.clone(),
+        None => return Err(StdError::generic_err("Self registration is not enabled")),
+    };
+
+    if env.block.time > expiry {
+        return Err(StdError::generic_err("Attestation expired"));
+    }
+
+    // the attestation binds the cred_id to the sender's address on this
+    // contract, for the cred_id's next registration only
+    let attestation = IdentityAttestation {
+        contract_address: env.contract.address.clone(),
+        cred_id: cred_id.clone(),
+        scrt_address: env.message.sender.clone(),
+        registrations: registrations_read(&deps.storage).may_load(cred_id.as_bytes())?.unwrap_or(0),
+        expiry,
+    };
+    let message = to_vec(&attestation)?;
+    if !verify_signature(&message, signature.as_slice(), registrar_pubkey.as_slice())? {
+        return Err(StdError::generic_err("Invalid attestation"));
+    }
+
+    register_user(deps, state, cred_id, &env.message.sender, alias)
+}
+
+pub fn try_set_oracle_key<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pubkey: Option<Binary>,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config(&mut deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Owner, &sender_address_raw)?;
+
+    // the nonce carries over, so payloads signed by a previous key stay used
+    state.oracle_pubkey = pubkey;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse::default())
+}
+
+pub fn try_set_uniqueness<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    uniqueness: Uniqueness,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config(&mut deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Owner, &sender_address_raw)?;
+
+    state.uniqueness = uniqueness;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse::default())
+}
+
+pub fn try_set_registrar_key<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pubkey: Option<Binary>,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config(&mut deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Owner, &sender_address_raw)?;
+
+    state.registrar_pubkey = pubkey;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse::default())
+}
+
+fn register_user<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    mut state: State,
+    cred_id: String,
+    scrt_address: &HumanAddr,
+    alias: Option<String>,
+) -> StdResult<HandleResponse> {
+    // user must not exist
+    let key = cred_id.as_bytes();
+    let registered = match user_cred_read(&deps.storage).may_load(key)? {
+        Some(_) => Some(true),
+        None => Some(false),
+    }.unwrap();
+    if registered {
+        return Err(StdError::GenericErr {
+            msg: "User already exists".to_string(), backtrace: None })
+    }
+
+    let scrt_address_raw = deps.api.canonical_address(scrt_address)?;
+    assert_address_available(&deps.storage, &state, &scrt_address_raw, &cred_id)?;
+    if let Some(alias) = &alias {
+        add_alias(&mut deps.storage, state.uniqueness.aliases, alias, &cred_id)?;
+    }
+
+    // a cred_id registering again picks up where it was deregistered
+    let mut cred = match deregistered_cred_read(&deps.storage).may_load(key)? {
+        Some(cred) => {
+            deregistered_cred(&mut deps.storage).remove(key);
+            UserCred { scrt_address: scrt_address_raw, alias, ..cred }
+        }
+        None => UserCred{
+            cred_id: cred_id.to_string(),
+            scrt_address: scrt_address_raw,
+            alias,
+            total_allocated: Uint128::zero(),
+            claimable: Uint128::zero(),
+            claimed: Uint128::zero(),
+            token_balances: vec![],
+            debt: Uint128::zero(),
+            allocations: vec![],
+        },
+    };
+
+    // release anything allocated before the user registered
+    let mut messages: Vec<CosmosMsg> = vec![];
+    let mut released = vec![];
+    let pending = escrow_read(&deps.storage).may_load(key)?;
+    if let Some(pending) = &pending {
+        for token in state.token_keys() {
+            let amount = pending.amount(&token);
+            if amount.is_zero() {
+                continue;
+            }
+            released.push(log("token", state.token_contract(&token)?.address.as_str()));
+            released.push(log("amount", amount.to_string()));
+            let payable = credit_user(&mut state, &mut cred, &token, amount)?;
+            state.update_totals(&token, |totals| {
+                totals.total_escrowed = (totals.total_escrowed - amount)?;
+                Ok(())
+            })?;
+
+            if !payable.is_zero() {
+                messages.push(payout(&mut state, &token, payable, scrt_address.clone(), None)?);
+            }
+        }
+        escrow(&mut deps.storage).remove(key);
+    }
+
+    user_cred(&mut deps.storage).save(key, &cred)?;
+    add_address(&mut deps.storage, &cred.scrt_address, &cred_id)?;
+    index_user(&mut deps.storage, &mut state, &cred_id)?;
+    let count = registrations_read(&deps.storage).may_load(key)?.unwrap_or(0);
+    registrations(&mut deps.storage).save(key, &(count + 1))?;
+
+    state.total_users += 1;
+    config(&mut deps.storage).save(&state)?;
+
+    if pending.is_none() {
+        return Ok(HandleResponse::default());
+    }
+
+    // a token and amount for each reward token released
+    let mut log_entries = vec![
+        log("action", "release-escrow"),
+        log("cred_id", &cred_id),
+    ];
+    log_entries.extend(released);
+
+    Ok(HandleResponse {
+        messages,
+        log: log_entries,
+        data: None,
+    })
+}
+
+pub fn try_update_user_address<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    cred_id: String,
+    scrt_address: &HumanAddr,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let state = config_read(&deps.storage).load()?;
+
+    let key = cred_id.as_bytes();
+    let mut cred = match load_cred(&deps.storage, key)? {
+        Some(cred) => cred,
+        None => return Err(StdError::generic_err("User not registered")),
+    };
+
+    // a registrar, or the user from their current address
+    if sender_address_raw != cred.scrt_address {
+        assert_role(&deps.storage, &state, Role::Registrar, &sender_address_raw)?;
+    }
+
+    let old_address = deps.api.human_address(&cred.scrt_address)?;
+    let scrt_address_raw = deps.api.canonical_address(scrt_address)?;
+    assert_address_available(&deps.storage, &state, &scrt_address_raw, &cred_id)?;
+    remove_address(&mut deps.storage, &cred.scrt_address, &cred_id)?;
+    add_address(&mut deps.storage, &scrt_address_raw, &cred_id)?;
+
+    cred.scrt_address = scrt_address_raw;
+    user_cred(&mut deps.storage).save(key, &cred)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "update-user-address"),
+            log("account", env.message.sender.as_str()),
+            log("cred_id", &cred_id),
+            log("old_address", old_address.as_str()),
+            log("new_address", scrt_address.as_str()),
+        ],
+        data: None,
+    })
+}
+
+// removes the address binding of a cred_id, its totals and allocation records
+// are kept and anything allocated afterwards, or still unclaimed, is escrowed
+// again until the cred_id registers a new address
+pub fn try_deregister_user<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    cred_id: String,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config_read(&deps.storage).load()?;
+
+    let key = cred_id.as_bytes();
+    let mut cred = match load_cred(&deps.storage, key)? {
+        Some(cred) => cred,
+        None => return Err(StdError::generic_err("User not registered")),
+    };
+
+    if sender_address_raw != cred.scrt_address {
+        assert_role(&deps.storage, &state, Role::Registrar, &sender_address_raw)?;
+    }
+
+    // the unclaimed balance is credited again when it is released from escrow
+    for token in state.token_keys() {
+        let mut balance = cred.balance(&token);
+        let claimable = balance.claimable;
+        if claimable.is_zero() {
+            continue;
+        }
+        escrow_allocation(&mut deps.storage, &mut state, &cred_id, &token, claimable)?;
+        state.update_totals(&token, |totals| {
+            totals.total_unclaimed = (totals.total_unclaimed - claimable)?;
+            Ok(())
+        })?;
+        balance.total_allocated = (balance.total_allocated - claimable)?;
+        balance.claimable = Uint128::zero();
+        cred.set_balance(balance);
+    }
+
+    let address = deps.api.human_address(&cred.scrt_address)?;
+    remove_address(&mut deps.storage, &cred.scrt_address, &cred_id)?;
+    if let Some(alias) = &cred.alias {
+        remove_alias(&mut deps.storage, alias, &cred_id)?;
+    }
+    user_cred(&mut deps.storage).remove(key);
+    deregistered_cred(&mut deps.storage).save(key, &cred)?;
+    user_position(&mut deps.storage).remove(key);
+
+    state.total_users -= 1;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "deregister-user"),
+            log("account", env.message.sender.as_str()),
+            log("cred_id", &cred_id),
+            log("address", address.as_str()),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_claim<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    amount: Option<Uint128>,
+    token: Option<HumanAddr>,
+    cred_id: Option<String>,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config(&mut deps.storage).load()?;
+    let token = state.token_key(token)?;
+
+    let cred_id = sender_cred_id(&deps.storage, &sender_address_raw, cred_id)?;
+    let key = cred_id.as_bytes();
+    let mut cred = load_cred(&deps.storage, key)?.ok_or_else(|| StdError::generic_err("User not registered"))?;
+    release_vested(&mut deps.storage, &mut state, &mut cred, &token, env.block.time)?;
+    let mut balance = cred.balance(&token);
+
+    let amount = amount.unwrap_or(balance.claimable);
+    if amount.is_zero() {
+        return Err(StdError::generic_err("Nothing to claim"));
+    }
+    if amount > balance.claimable {
+        return Err(StdError::generic_err("Insufficient claimable balance"));
+    }
+
+    balance.claimable = (balance.claimable - amount)?;
+    balance.claimed += amount;
+    cred.set_balance(balance);
+    state.update_totals(&token, |totals| {
+        totals.total_unclaimed = (totals.total_unclaimed - amount)?;
+        Ok(())
+    })?;
+
+    let messages = vec![payout(&mut state, &token, amount, env.message.sender.clone(), None)?];
+
+    user_cred(&mut deps.storage).save(key, &cred)?;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "claim"),
+            log("account", env.message.sender.as_str()),
+            log("amount", amount.to_string()),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_add_reward_token<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    token: ContractInfo,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config(&mut deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Owner, &sender_address_raw)?;
+
+    if state.payout_mode == PayoutMode::Native {
+        return Err(StdError::generic_err("Reward tokens are not supported in native payout mode"));
+    }
+    if state.token_key(Some(token.address.clone())).is_ok() {
+        return Err(StdError::generic_err("Reward token already registered"));
+    }
+
+    let mut messages = vec![];
+    if state.payout_mode == PayoutMode::Transfer {
+        messages.push(snip20::HandleMsg::RegisterReceive {
+            code_hash: env.contract_code_hash,
+            padding: None,
+        }.to_cosmos_msg(BLOCK_SIZE, &token)?);
+    }
+
+    state.reward_tokens.push(RewardToken {
+        contract: token.clone(),
+        totals: TokenTotals::default(),
+        caps: Caps::default(),
+    });
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "add-reward-token"),
+            log("token", token.address.as_str()),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_fund<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    amount: Uint128,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config(&mut deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Owner, &sender_address_raw)?;
+
+    if state.payout_mode != PayoutMode::Native {
+        return Err(StdError::generic_err("Contract does not hold a native treasury"));
+    }
+    if amount.is_zero() {
+        return Err(StdError::generic_err("Nothing to fund"));
+    }
+    assert_sent_sufficient_coin(&env.message.sent_funds, Some(coin(amount.u128(), NATIVE_DENOM)))?;
+    // the treasury only tracks amount, anything sent on top would be stuck
+    let sent: u128 = env.message.sent_funds
+        .iter()
+        .filter(|sent| sent.denom == NATIVE_DENOM)
+        .map(|sent| sent.amount.u128())
+        .sum();
+    if sent > amount.u128() {
+        return Err(StdError::generic_err("Sent more funds than the amount"));
+    }
+
+    state.treasury_balance += amount;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "fund-treasury"),
+            log("from", env.message.sender.as_str()),
+            log("amount", amount.to_string()),
+        ],
+        data: None,
+    })
+}
+
+// tokens sent to the contract fund the treasury
+pub fn try_receive<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    from: HumanAddr,
+    amount: Uint128,
+) -> StdResult<HandleResponse> {
+    let mut state = config(&mut deps.storage).load()?;
+    // the sender is the token that was received
+    let token = state.token_key(Some(env.message.sender.clone()))
+        .map_err(|_| StdError::Unauthorized { backtrace: None })?;
+    if state.payout_mode != PayoutMode::Transfer {
+        return Err(StdError::generic_err("Contract does not hold a token treasury"));
+    }
+
+    state.update_totals(&token, |totals| {
+        totals.treasury_balance += amount;
+        Ok(())
+    })?;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "fund-treasury"),
+            log("token", env.message.sender.as_str()),
+            log("from", from.as_str()),
+            log("amount", amount.to_string()),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_set_status<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    status: ContractStatus,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config(&mut deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Pauser, &sender_address_raw)?;
+
+    let log_status = log("status", status.as_str());
+    state.status = status;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "set-status"),
+            log("account", env.message.sender.as_str()),
+            log_status,
+        ],
+        data: None,
+    })
+}
+
+pub fn try_set_caps<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    caps: Caps,
+    token: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config(&mut deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Owner, &sender_address_raw)?;
+
+    let token = state.token_key(token)?;
+    state.set_caps(&token, caps)?;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse::default())
+}
+
+// reverses an allocation, taking it back from vesting, escrowed and claimable
+// balances in that order, whatever was already paid out becomes a debt of the
+// cred offset against its future allocations
+pub fn try_revoke_allocation<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    cred_id: String,
+    allocation_id: String,
+    reason: String,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config(&mut deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Allocator, &sender_address_raw)?;
+
+    let allocation = match allocations_read(&deps.storage, &cred_id).may_load(allocation_id.as_bytes())? {
+        Some(allocation) => allocation,
+        None => return Err(StdError::generic_err("Allocation does not exist")),
+    };
+    let token = allocation.token.clone();
+    let key = cred_id.as_bytes();
+    let mut remaining = allocation.amount;
+
+    if let Some(mut schedules) = vesting_read(&deps.storage).may_load(key)? {
+        if let Some(index) = schedules.iter().position(|schedule| schedule.allocation_id == allocation_id) {
+            let schedule = schedules.remove(index);
+            let unreleased = (schedule.amount - schedule.released)?;
+            state.update_totals(&token, |totals| {
+                totals.total_vesting = (totals.total_vesting - unreleased)?;
+                Ok(())
+            })?;
+            remaining = (remaining - unreleased)?;
+            vesting(&mut deps.storage).save(key, &schedules)?;
+        }
+    }
+
+    if let Some(mut cred) = load_cred(&deps.storage, key)? {
+        let mut balance = cred.balance(&token);
+        balance.total_allocated = (balance.total_allocated - remaining)?;
+
+        let unclaimed = std::cmp::min(balance.claimable, remaining);
+        balance.claimable = (balance.claimable - unclaimed)?;
+        state.update_totals(&token, |totals| {
+            totals.total_unclaimed = (totals.total_unclaimed - unclaimed)?;
+            Ok(())
+        })?;
+
+        remaining = (remaining - unclaimed)?;
+        balance.debt += remaining;
+        cred.set_balance(balance);
+        user_cred(&mut deps.storage).save(key, &cred)?;
+    } else {
+        if let Some(mut pending) = escrow_read(&deps.storage).may_load(key)? {
+            let escrowed = std::cmp::min(pending.amount(&token), remaining);
+            // nothing may be escrowed in the allocation's token at all
+            if !escrowed.is_zero() {
+                pending.sub(&token, escrowed)?;
+                escrow(&mut deps.storage).save(key, &pending)?;
+                state.update_totals(&token, |totals| {
+                    totals.total_escrowed = (totals.total_escrowed - escrowed)?;
+                    Ok(())
+                })?;
+                remaining = (remaining - escrowed)?;
+            }
+        }
+
+        // a deregistered cred owes what it was paid, once it registers again
+        if let Some(mut cred) = deregistered_cred_read(&deps.storage).may_load(key)? {
+            let mut balance = cred.balance(&token);
+            balance.total_allocated = (balance.total_allocated - remaining)?;
+            balance.debt += remaining;
+            cred.set_balance(balance);
+            deregistered_cred(&mut deps.storage).save(key, &cred)?;
+        }
+    }
+    // whatever is left had already been paid out
+    let debt = remaining;
+
+    allocations(&mut deps.storage, &cred_id).remove(allocation_id.as_bytes());
+    allocation_position(&mut deps.storage, &cred_id).remove(allocation_id.as_bytes());
+    state.update_totals(&token, |totals| {
+        totals.total_cred = (totals.total_cred - allocation.amount)?;
+        Ok(())
+    })?;
+    state.sub_policy_total(&token, &allocation.policy, allocation.amount)?;
+
+    let id = allocation_id.as_bytes();
+    if let Some(total) = allocation_totals_read(&deps.storage, &allocation.token).may_load(id)? {
+        allocation_totals(&mut deps.storage, &allocation.token).save(id, &(total - allocation.amount)?)?;
+    }
+    // allocations made before a distribution of their id never counted towards it
+    if allocation.in_distribution {
+        if let Some(mut distribution) = distributions_read(&deps.storage).may_load(id)? {
+            distribution.allocated = (distribution.allocated - allocation.amount)?;
+            distribution.recipients = distribution.recipients
+                .checked_sub(1)
+                .ok_or_else(|| StdError::generic_err("Distribution has no recipients"))?;
+            distributions(&mut deps.storage).save(id, &distribution)?;
+        }
+    }
+
+    let position = revocations_indexed_read(&deps.storage).may_load(key)?.unwrap_or(0);
+    revocations(&mut deps.storage, &cred_id).save(&position.to_be_bytes(), &Revocation {
+        allocation_id: allocation_id.clone(),
+        token,
+        amount: allocation.amount,
+        debt,
+        reason,
+        revoked_by: env.message.sender.clone(),
+        time: env.block.time,
+    })?;
+    revocations_indexed(&mut deps.storage).save(key, &(position + 1))?;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "revoke-allocation"),
+            log("account", env.message.sender.as_str()),
+            log("cred_id", &cred_id),
+            log("allocation_id", &allocation_id),
+            log("amount", allocation.amount.to_string()),
+            log("debt", debt.to_string()),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_publish_distribution_root<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    distribution_id: String,
+    merkle_root: Binary,
+    total: Uint128,
+    token: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let state = config_read(&deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Allocator, &sender_address_raw)?;
+    let token = state.token_key(token)?;
+
+    if merkle_root.len() != 32 {
+        return Err(StdError::generic_err("Merkle root must be 32 bytes"));
+    }
+    let key = distribution_id.as_bytes();
+    if merkle_roots_read(&deps.storage).may_load(key)?.is_some() {
+        return Err(StdError::generic_err("Distribution root already published"));
+    }
+    // claims count towards a distribution of the same id, in its token
+    if let Some(distribution) = distributions_read(&deps.storage).may_load(key)? {
+        if distribution.token != token {
+            return Err(StdError::generic_err("Token does not match distribution"));
+        }
+    }
+    assert_treasury_covers(&state, &token, total)?;
+
+    let root = MerkleRoot {
+        distribution_id: distribution_id.clone(),
+        merkle_root,
+        token,
+        total,
+        claimed: Uint128::zero(),
+    };
+    merkle_roots(&mut deps.storage).save(key, &root)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "publish-distribution-root"),
+            log("distribution_id", &distribution_id),
+            log("total", total.to_string()),
+        ],
+        data: None,
+    })
+}
+
+// pays a registered user their share of a distribution root right away, the
+// share is recorded as an Immediate allocation of distribution_id
+pub fn try_claim_from_root<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    distribution_id: String,
+    amount: Uint128,
+    proof: Vec<Binary>,
+    cred_id: Option<String>,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config(&mut deps.storage).load()?;
+
+    let cred_id = sender_cred_id(&deps.storage, &sender_address_raw, cred_id)?;
+    let mut root = match merkle_roots_read(&deps.storage).may_load(distribution_id.as_bytes())? {
+        Some(root) => root,
+        None => return Err(StdError::generic_err("Distribution root does not exist")),
+    };
+    if root_claims_read(&deps.storage, &distribution_id).may_load(cred_id.as_bytes())?.is_some()
+        || is_allocated(&deps.storage, &cred_id, &distribution_id)? {
+        return Err(StdError::generic_err("Already claimed"));
+    }
+
+    let leaf = sha_256(&to_vec(&Receipt { cred_id: cred_id.clone(), amount })?);
+    if !verify_merkle_proof(root.merkle_root.as_slice(), leaf, &proof) {
+        return Err(StdError::generic_err("Invalid proof"));
+    }
+    root.claimed += amount;
+    if root.claimed > root.total {
+        return Err(StdError::generic_err("Claim exceeds distribution total"));
+    }
+
+    let token = root.token.clone();
+    assert_treasury_covers(&state, &token, amount)?;
+    apply_caps(&mut deps.storage, &state, env.block.time, &distribution_id, &token, &[amount])?;
+    // a distribution of the same id limits its root like any other allocation
+    let in_distribution = apply_distribution(&mut deps.storage, &distribution_id, &PolicyType::Immediate, &token, &[amount])?;
+
+    let allocation = Allocation {
+        policy: PolicyType::Immediate,
+        amount,
+        allocation_id: distribution_id.clone(),
+        token: token.clone(),
+        in_distribution,
+    };
+    save_allocation(&mut deps.storage, &cred_id, &allocation)?;
+    state.update_totals(&token, |totals| {
+        totals.total_cred += amount;
+        Ok(())
+    })?;
+    state.add_policy_total(&token, &allocation.policy, amount)?;
+
+    let key = cred_id.as_bytes();
+    let mut cred = load_cred(&deps.storage, key)?.ok_or_else(|| StdError::generic_err("User not registered"))?;
+    let mut balance = cred.balance(&token);
+    balance.total_allocated += amount;
+    let due = balance.offset_debt(amount);
+    balance.claimed += due;
+    cred.set_balance(balance);
+
+    let mut messages: Vec<CosmosMsg> = vec![];
+    if !due.is_zero() {
+        messages.push(payout(&mut state, &token, due, env.message.sender.clone(), Some(distribution_id.clone()))?);
+    }
+
+    merkle_roots(&mut deps.storage).save(distribution_id.as_bytes(), &root)?;
+    root_claims(&mut deps.storage, &distribution_id).save(cred_id.as_bytes(), &true)?;
+    user_cred(&mut deps.storage).save(key, &cred)?;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "claim-from-root"),
+            log("account", env.message.sender.as_str()),
+            log("distribution_id", &distribution_id),
+            log("amount", amount.to_string()),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_set_balanced_vesting<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    terms: Option<VestingTerms>,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config(&mut deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Owner, &sender_address_raw)?;
+
+    state.balanced_vesting = terms;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse::default())
+}
+
+pub fn try_create_distribution<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    id: String,
+    policy: PolicyType,
+    budget: Uint128,
+    timestamp: u64,
+    token: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let state = config_read(&deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Allocator, &sender_address_raw)?;
+    let token = state.token_key(token)?;
+
+    if distributions_read(&deps.storage).may_load(id.as_bytes())?.is_some() {
+        return Err(StdError::generic_err("Distribution already exists"));
+    }
+    // allocations made before the distribution wouldn't count towards its budget
+    for token in state.token_keys() {
+        if allocation_totals_read(&deps.storage, &token).may_load(id.as_bytes())?.is_some() {
+            return Err(StdError::generic_err("Allocation id already used"));
+        }
+    }
+
+    let distribution = Distribution {
+        id: id.clone(),
+        policy,
+        token,
+        budget,
+        timestamp,
+        allocated: Uint128::zero(),
+        recipients: 0,
+        finalized: false,
+    };
+    distributions(&mut deps.storage).save(id.as_bytes(), &distribution)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "create-distribution"),
+            log("id", &id),
+            log("budget", budget.to_string()),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_finalize_distribution<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    id: String,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let state = config_read(&deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Allocator, &sender_address_raw)?;
+
+    let mut distribution = match distributions_read(&deps.storage).may_load(id.as_bytes())? {
+        Some(distribution) => distribution,
+        None => return Err(StdError::generic_err("Distribution does not exist")),
+    };
+    if distribution.finalized {
+        return Err(StdError::generic_err("Distribution is finalized"));
+    }
+    distribution.finalized = true;
+    distributions(&mut deps.storage).save(id.as_bytes(), &distribution)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "finalize-distribution"),
+            log("id", &id),
+            log("allocated", distribution.allocated.to_string()),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_set_claim_mode<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    claim_mode: ClaimMode,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config(&mut deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Owner, &sender_address_raw)?;
+
+    // balances already claimable stay claimable after switching to push
+    state.claim_mode = claim_mode;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse::default())
+}
+
+// moves the allocations of UserCred records saved before allocations had
+// their own bucket into that bucket, a record without legacy allocations is
+// left untouched so the message can safely be repeated
+pub fn try_migrate_allocations<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    cred_ids: Vec<String>,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config_read(&deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Owner, &sender_address_raw)?;
+
+    // a State saved by the first release has neither, permits check the
+    // address and CreateViewingKey needs the seed
+    if state.contract_address.as_str().is_empty() {
+        state.contract_address = env.contract.address.clone();
+    }
+    if prng_seed_read(&deps.storage).may_load()?.is_none() {
+        prng_seed(&mut deps.storage).save(&Binary(sha_256(&to_vec(&env)?).to_vec()))?;
+    }
+
+    let mut migrated = 0u64;
+    for cred_id in cred_ids {
+        let key = cred_id.as_bytes();
+        let mut cred = match user_cred_read(&deps.storage).may_load(key)? {
+            Some(cred) => cred,
+            None => return Err(StdError::generic_err(format!("User does not exist: {}", cred_id))),
+        };
+        // users registered before the index and lookups existed, whose
+        // addresses and aliases were never checked for uniqueness
+        index_user(&mut deps.storage, &mut state, &cred_id)?;
+        add_address(&mut deps.storage, &cred.scrt_address, &cred_id)?;
+        if let Some(alias) = &cred.alias {
+            add_alias(&mut deps.storage, false, alias, &cred_id)?;
+        }
+        if cred.allocations.is_empty() {
+            continue;
+        }
+
+        for allocation in cred.allocations.iter() {
+            save_allocation(&mut deps.storage, &cred_id, allocation)?;
+        }
+        cred.allocations.clear();
+        user_cred(&mut deps.storage).save(key, &cred)?;
+        migrated += 1;
+    }
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "migrate-allocations"),
+            log("migrated", migrated.to_string()),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_propose_owner<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    address: &HumanAddr,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config(&mut deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Owner, &sender_address_raw)?;
+
+    // ownership only moves once the proposed owner accepts, so a mistyped
+    // address can't lock the owner out
+    state.pending_owner = Some(deps.api.canonical_address(address)?);
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "propose-owner"),
+            log("pending_owner", address.as_str()),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_accept_ownership<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config(&mut deps.storage).load()?;
+    if state.pending_owner != Some(sender_address_raw.clone()) {
+        return Err(StdError::Unauthorized { backtrace: None });
+    }
+
+    state.owner = sender_address_raw;
+    state.pending_owner = None;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "accept-ownership"),
+            log("owner", env.message.sender.as_str()),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_cancel_ownership_proposal<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let mut state = config(&mut deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Owner, &sender_address_raw)?;
+    if state.pending_owner.is_none() {
+        return Err(StdError::generic_err("No pending owner"));
+    }
+
+    state.pending_owner = None;
+    config(&mut deps.storage).save(&state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "cancel-ownership-proposal")],
+        data: None,
+    })
+}
+
+pub fn try_grant_role<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    role: Role,
+    address: &HumanAddr,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let state = config_read(&deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Owner, &sender_address_raw)?;
+
+    if role == Role::Owner {
+        return Err(StdError::generic_err("Ownership is transferred with propose_owner"));
+    }
+
+    let address_raw = deps.api.canonical_address(address)?;
+    let mut holders = role_holders_read(&deps.storage).may_load(role.as_str().as_bytes())?.unwrap_or_default();
+    if holders.contains(&address_raw) {
+        return Err(StdError::generic_err("Address already holds role"));
+    }
+    holders.push(address_raw);
+    role_holders(&mut deps.storage).save(role.as_str().as_bytes(), &holders)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "grant-role"),
+            log("role", role.as_str()),
+            log("address", address.as_str()),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_revoke_role<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    role: Role,
+    address: &HumanAddr,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    let state = config_read(&deps.storage).load()?;
+    assert_role(&deps.storage, &state, Role::Owner, &sender_address_raw)?;
+
+    let address_raw = deps.api.canonical_address(address)?;
+    let mut holders = role_holders_read(&deps.storage).may_load(role.as_str().as_bytes())?.unwrap_or_default();
+    if !holders.contains(&address_raw) {
+        return Err(StdError::generic_err("Address does not hold role"));
+    }
+    holders.retain(|holder| holder != &address_raw);
+    role_holders(&mut deps.storage).save(role.as_str().as_bytes(), &holders)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "revoke-role"),
+            log("role", role.as_str()),
+            log("address", address.as_str()),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_create_viewing_key<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    entropy: String,
+) -> StdResult<HandleResponse> {
+    let seed = prng_seed_read(&deps.storage).load()?;
+    let key = ViewingKey::new(&env, seed.as_slice(), entropy.as_bytes());
+
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    viewing_key(&mut deps.storage).save(sender_address_raw.as_slice(), &Binary(key.to_hashed().to_vec()))?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::CreateViewingKey { key })?),
+    })
+}
+
+pub fn try_set_viewing_key<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    key: String,
+) -> StdResult<HandleResponse> {
+    let key = ViewingKey(key);
+
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    viewing_key(&mut deps.storage).save(sender_address_raw.as_slice(), &Binary(key.to_hashed().to_vec()))?;
+
+    Ok(HandleResponse::default())
+}
+
+pub fn try_revoke_permit<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    permit_name: String,
+) -> StdResult<HandleResponse> {
+    let sender_address_raw = deps.api.canonical_address(&env.message.sender)?;
+    revoked_permits(&mut deps.storage, &sender_address_raw).save(permit_name.as_bytes(), &true)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "revoke-permit"),
+            log("permit_name", permit_name),
+        ],
+        data: None,
+    })
+}
+
+pub fn query<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    msg: QueryMsg,
+) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => to_binary(&config_read(&deps.storage).load()?),
+        QueryMsg::IsCredRegistered { cred_id } => to_binary(&query_user_registered(deps, cred_id)?),
+        QueryMsg::PendingOwner {} => to_binary(&query_pending_owner(deps)?),
+        QueryMsg::RoleHolders { role } => to_binary(&query_role_holders(deps, role)?),
+        QueryMsg::Treasury { token } => to_binary(&query_treasury(deps, token)?),
+        QueryMsg::RewardTokens {} => to_binary(&query_reward_tokens(deps)?),
+        QueryMsg::CapHeadroom { allocation_id, cred_id, time, token } =>
+            to_binary(&query_cap_headroom(deps, allocation_id, cred_id, time, token)?),
+        QueryMsg::Distribution { id } => to_binary(&query_distribution(deps, id)?),
+        QueryMsg::CredIdByAlias { alias } => to_binary(&CredIdsResponse {
+            cred_ids: alias_creds_read(&deps.storage).may_load(alias.as_bytes())?.unwrap_or_default(),
+        }),
+        QueryMsg::DistributionRoot { distribution_id } =>
+            to_binary(&query_distribution_root(deps, distribution_id)?),
+        QueryMsg::ContractStatus {} => to_binary(&ContractStatusResponse {
+            status: config_read(&deps.storage).load()?.status,
+        }),
+        QueryMsg::WithPermit { permit, query } => permit_queries(deps, permit, query),
+        _ => viewing_keys_queries(deps, msg),
+    }
+}
+
+fn viewing_keys_queries<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    msg: QueryMsg,
+) -> StdResult<Binary> {
+    let (address, key) = msg.get_validation_params()?;
+    let address_raw = deps.api.canonical_address(address)?;
+
+    let valid = match viewing_key_read(&deps.storage).may_load(address_raw.as_slice())? {
+        Some(expected) => key.check_viewing_key(expected.as_slice()),
+        None => {
+            // compare against a dummy so a missing key takes as long as a wrong one
+            key.check_viewing_key(&[0u8; 32]);
+            false
+        }
+    };
+    if !valid {
+        return Err(StdError::generic_err("Wrong viewing key for this address or viewing key not set"));
+    }
+
+    match msg {
+        QueryMsg::GetTotalAllocated { cred_id, token, .. } => {
+            assert_cred_access(deps, &address_raw, &cred_id)?;
+            to_binary(&query_total_allocated(deps, cred_id, token)?)
+        }
+        QueryMsg::GetUserCred { cred_id, .. } => {
+            assert_cred_access(deps, &address_raw, &cred_id)?;
+            to_binary(&query_user_cred(deps, cred_id)?)
+        }
+        QueryMsg::IsAllocated { cred_id, allocation_id, .. } => {
+            assert_cred_access(deps, &address_raw, &cred_id)?;
+            to_binary(&query_allocated(deps, cred_id, allocation_id)?)
+        }
+        QueryMsg::GetEscrow { cred_id, .. } => {
+            assert_cred_access(deps, &address_raw, &cred_id)?;
+            to_binary(&query_escrow(deps, cred_id)?)
+        }
+        QueryMsg::GetClaimable { cred_id, token, .. } => {
+            assert_cred_access(deps, &address_raw, &cred_id)?;
+            to_binary(&query_claimable(deps, cred_id, token)?)
+        }
+        QueryMsg::GetVesting { cred_id, time, .. } => {
+            assert_cred_access(deps, &address_raw, &cred_id)?;
+            to_binary(&query_vesting(deps, cred_id, time)?)
+        }
+        QueryMsg::GetRevocations { cred_id, start_after, limit, .. } => {
+            assert_cred_access(deps, &address_raw, &cred_id)?;
+            to_binary(&query_revocations(deps, cred_id, start_after, limit)?)
+        }
+        QueryMsg::GetAllocations { cred_id, start_after, limit, policy, .. } => {
+            assert_cred_access(deps, &address_raw, &cred_id)?;
+            to_binary(&query_allocations(deps, cred_id, start_after, limit, policy)?)
+        }
+        QueryMsg::CredIdByAddress { .. } => to_binary(&query_cred_id_by_address(deps, &address_raw)?),
+        QueryMsg::ListUsers { start_after, limit, .. } => {
+            assert_cred_admin(deps, &address_raw)?;
+            to_binary(&query_list_users(deps, start_after, limit)?)
+        }
+        _ => Err(StdError::generic_err("This query type does not require authentication")),
+    }
+}
+
+fn permit_queries<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    permit: Permit,
+    query: QueryWithPermit,
+) -> StdResult<Binary> {
+    let contract_address = config_read(&deps.storage).load()?.contract_address;
+    let address = permit::validate(deps, &permit, &contract_address)?;
+    let address_raw = deps.api.canonical_address(&address)?;
+
+    let required = match query {
+        QueryWithPermit::IsAllocated { .. }
+        | QueryWithPermit::GetRevocations { .. }
+        | QueryWithPermit::GetAllocations { .. } => Permission::Allocations,
+        QueryWithPermit::ListUsers { .. } => Permission::Owner,
+        _ => Permission::Cred,
+    };
+    if !permit.params.permissions.contains(&required) && !permit.params.permissions.contains(&Permission::Owner) {
+        return Err(StdError::generic_err(format!(
+            "No permission to query, got permissions {:?}", permit.params.permissions
+        )));
+    }
+
+    match query {
+        QueryWithPermit::GetTotalAllocated { cred_id, token } => {
+            assert_cred_access(deps, &address_raw, &cred_id)?;
+            to_binary(&query_total_allocated(deps, cred_id, token)?)
+        }
+        QueryWithPermit::GetUserCred { cred_id } => {
+            assert_cred_access(deps, &address_raw, &cred_id)?;
+            to_binary(&query_user_cred(deps, cred_id)?)
+        }
+        QueryWithPermit::IsAllocated { cred_id, allocation_id } => {
+            assert_cred_access(deps, &address_raw, &cred_id)?;
+            to_binary(&query_allocated(deps, cred_id, allocation_id)?)
+        }
+        QueryWithPermit::GetEscrow { cred_id } => {
+            assert_cred_access(deps, &address_raw, &cred_id)?;
+            to_binary(&query_escrow(deps, cred_id)?)
+        }
+        QueryWithPermit::GetClaimable { cred_id, token } => {
+            assert_cred_access(deps, &address_raw, &cred_id)?;
+            to_binary(&query_claimable(deps, cred_id, token)?)
+        }
+        QueryWithPermit::GetVesting { cred_id, time } => {
+            assert_cred_access(deps, &address_raw, &cred_id)?;
+            to_binary(&query_vesting(deps, cred_id, time)?)
+        }
+        QueryWithPermit::GetRevocations { cred_id, start_after, limit } => {
+            assert_cred_access(deps, &address_raw, &cred_id)?;
+            to_binary(&query_revocations(deps, cred_id, start_after, limit)?)
+        }
+        QueryWithPermit::GetAllocations { cred_id, start_after, limit, policy } => {
+            assert_cred_access(deps, &address_raw, &cred_id)?;
+            to_binary(&query_allocations(deps, cred_id, start_after, limit, policy)?)
+        }
+        QueryWithPermit::CredIdByAddress {} => to_binary(&query_cred_id_by_address(deps, &address_raw)?),
+        QueryWithPermit::ListUsers { start_after, limit } => {
+            assert_cred_admin(deps, &address_raw)?;
+            to_binary(&query_list_users(deps, start_after, limit)?)
+        }
+    }
+}
+
+// a cred can be read by its registered address, and by the registrar and
+// allocator roles who manage every cred
+fn assert_cred_access<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>, address: &CanonicalAddr, cred_id: &str) -> StdResult<()> {
+    let cred_ids = address_cred_read(&deps.storage).may_load(address.as_slice())?.unwrap_or_default();
+    if cred_ids.iter().any(|registered| registered == cred_id) {
+        return Ok(());
+    }
+
+    assert_cred_admin(deps, address)
+}
+
+fn assert_cred_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>, address: &CanonicalAddr) -> StdResult<()> {
+    let state = config_read(&deps.storage).load()?;
+    if has_role(&deps.storage, &state, Role::Registrar, address)?
+        || has_role(&deps.storage, &state, Role::Allocator, address)? {
+        return Ok(());
+    }
+
+    Err(StdError::Unauthorized { backtrace: None })
+}
+
+fn query_user_registered<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>, id: String) -> StdResult<CredRegisteredResponse> {
+    let key = &id.as_bytes();
+    let registered = match user_cred_read(&deps.storage).may_load(key)? {
+        Some(_) => Some(true),
+        None => Some(false),
+    }.unwrap();
+    let registrations = registrations_read(&deps.storage).may_load(key)?.unwrap_or(0);
+
+    Ok(CredRegisteredResponse { registered, registrations })
+}
+
+fn query_allocated<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>, id: String, allocation_id: String) -> StdResult<CredAllocatedResponse> {
+    let key = &id.as_bytes();
+    if user_cred_read(&deps.storage).may_load(key)?.is_none()
+        && deregistered_cred_read(&deps.storage).may_load(key)?.is_none()
+        && escrow_read(&deps.storage).may_load(key)?.is_none() {
+        return Err(StdError::GenericErr { msg: "User does not exist".to_string(), backtrace: None });
+    }
+
+    Ok(CredAllocatedResponse { allocated: is_allocated(&deps.storage, &id, &allocation_id)? })
+}
+
+fn query_pending_owner<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>) -> StdResult<PendingOwnerResponse> {
+    let state = config_read(&deps.storage).load()?;
+    let pending_owner = match state.pending_owner {
+        Some(pending_owner) => Some(deps.api.human_address(&pending_owner)?),
+        None => None,
+    };
+
+    Ok(PendingOwnerResponse { pending_owner })
+}
+
+fn query_role_holders<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>, role: Role) -> StdResult<RoleHoldersResponse> {
+    let holders = match role {
+        Role::Owner => vec![config_read(&deps.storage).load()?.owner],
+        _ => role_holders_read(&deps.storage).may_load(role.as_str().as_bytes())?.unwrap_or_default(),
+    };
+    let holders = holders
+        .iter()
+        .map(|holder| deps.api.human_address(holder))
+        .collect::<StdResult<Vec<HumanAddr>>>()?;
+
+    Ok(RoleHoldersResponse { role, holders })
+}
+
+fn query_treasury<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>, token: Option<HumanAddr>) -> StdResult<TreasuryResponse> {
+    let state = config_read(&deps.storage).load()?;
+    let totals = state.totals(&state.token_key(token)?)?;
+
+    let native_balance = match state.payout_mode {
+        PayoutMode::Native => Some(deps.querier.query_balance(&state.contract_address, NATIVE_DENOM)?.amount),
+        _ => None,
+    };
+
+    Ok(TreasuryResponse {
+        payout_mode: state.payout_mode,
+        balance: totals.treasury_balance,
+        liabilities: totals.total_escrowed + totals.total_unclaimed + totals.total_vesting,
+        native_balance,
+    })
+}
+
+fn query_cap_headroom<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    allocation_id: String,
+    cred_id: Option<String>,
+    time: u64,
+    token: Option<HumanAddr>,
+) -> StdResult<CapHeadroomResponse> {
+    let state = config_read(&deps.storage).load()?;
+    let token = state.token_key(token)?;
+    let caps = state.caps(&token)?;
+    let headroom = |max: Option<Uint128>, used: Uint128| {
+        max.map(|max| (max - used).unwrap_or_else(|_| Uint128::zero()))
+    };
+
+    let allocated = allocation_totals_read(&deps.storage, &token)
+        .may_load(allocation_id.as_bytes())?
+        .unwrap_or_else(Uint128::zero);
+    // what cred_id already holds of allocation_id in token
+    let mut user_allocated = Uint128::zero();
+    if let Some(cred_id) = cred_id {
+        if let Some(allocation) = allocations_read(&deps.storage, &cred_id).may_load(allocation_id.as_bytes())? {
+            if allocation.token == token {
+                user_allocated = allocation.amount;
+            }
+        }
+    }
+    let entries = cap_window_read(&deps.storage).may_load()?.unwrap_or_default();
+
+    Ok(CapHeadroomResponse {
+        per_allocation: headroom(caps.max_per_allocation, allocated),
+        per_user: headroom(caps.max_per_user, user_allocated),
+        window: headroom(caps.max_per_window, window_total(&entries, caps, time, &token)),
+    })
+}
+
+fn query_distribution<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>, id: String) -> StdResult<DistributionResponse> {
+    let distribution = match distributions_read(&deps.storage).may_load(id.as_bytes())? {
+        Some(distribution) => distribution,
+        None => return Err(StdError::generic_err("Distribution does not exist")),
+    };
+    let remaining = (distribution.budget - distribution.allocated)?;
+
+    Ok(DistributionResponse { distribution, remaining })
+}
+
+fn query_distribution_root<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>, distribution_id: String) -> StdResult<DistributionRootResponse> {
+    let root = match merkle_roots_read(&deps.storage).may_load(distribution_id.as_bytes())? {
+        Some(root) => root,
+        None => return Err(StdError::generic_err("Distribution root does not exist")),
+    };
+    let remaining = (root.total - root.claimed)?;
+
+    Ok(DistributionRootResponse { root, remaining })
+}
+
+fn query_reward_tokens<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>) -> StdResult<RewardTokensResponse> {
+    let state = config_read(&deps.storage).load()?;
+
+    let mut tokens = vec![RewardToken {
+        contract: state.token_contract.clone(),
+        totals: state.totals(&None)?,
+        caps: state.caps.clone(),
+    }];
+    tokens.extend(state.reward_tokens);
+
+    Ok(RewardTokensResponse { tokens })
+}
+
+fn query_claimable<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>, id: String, token: Option<HumanAddr>) -> StdResult<ClaimableResponse> {
+    let cred = match user_cred_read(&deps.storage).may_load(id.as_bytes())? {
+        Some(cred) => cred,
+        None => return Err(StdError::generic_err("User is not registered")),
+    };
+    let token = config_read(&deps.storage).load()?.token_key(token)?;
+    let balance = cred.balance(&token);
+
+    Ok(ClaimableResponse { claimable: balance.claimable, claimed: balance.claimed })
+}
+
+fn query_vesting<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>, id: String, time: u64) -> StdResult<VestingResponse> {
+    let schedules = vesting_read(&deps.storage).may_load(id.as_bytes())?.unwrap_or_default();
+    let schedules = schedules
+        .into_iter()
+        .map(|schedule| {
+            let vested = schedule.vested(time);
+            Ok(VestingStatus {
+                unvested: (schedule.amount - vested)?,
+                allocation_id: schedule.allocation_id,
+                token: schedule.token,
+                amount: schedule.amount,
+                vested,
+                claimed: schedule.released,
+            })
+        })
+        .collect::<StdResult<Vec<VestingStatus>>>()?;
+
+    Ok(VestingResponse { schedules })
+}
+
+fn query_revocations<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    id: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<RevocationsResponse> {
+    let indexed = revocations_indexed_read(&deps.storage).may_load(id.as_bytes())?.unwrap_or(0);
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE) as u64;
+    let start = start_after.map(|position| position + 1).unwrap_or(0);
+    let end = indexed.min(start.saturating_add(limit));
+
+    let revocations = (start..end)
+        .map(|position| {
+            let revocation = revocations_read(&deps.storage, &id).load(&position.to_be_bytes())?;
+            Ok(RevocationRecord { position, revocation })
+        })
+        .collect::<StdResult<Vec<RevocationRecord>>>()?;
+
+    Ok(RevocationsResponse { revocations })
+}
+
+fn query_allocations<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    id: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+    policy: Option<PolicyType>,
+) -> StdResult<AllocationsResponse> {
+    let indexed = allocations_indexed_read(&deps.storage).may_load(id.as_bytes())?.unwrap_or(0);
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE) as usize;
+    let start = start_after.map(|position| position + 1).unwrap_or(0);
+
+    let end = indexed.min(start.saturating_add(MAX_SCAN_SIZE));
+
+    let mut records = vec![];
+    let mut scanned = None;
+    for position in start..end {
+        if records.len() >= limit {
+            break;
+        }
+        scanned = Some(position);
+        let allocation_id = allocation_index_read(&deps.storage, &id).load(&position.to_be_bytes())?;
+        let key = allocation_id.as_bytes();
+        // revoked, or allocated again further down the index
+        if allocation_position_read(&deps.storage, &id).may_load(key)? != Some(position) {
+            continue;
+        }
+        let allocation = allocations_read(&deps.storage, &id).load(key)?;
+        if let Some(policy) = &policy {
+            if std::mem::discriminant(&allocation.policy) != std::mem::discriminant(policy) {
+                continue;
+            }
+        }
+        records.push(AllocationRecord { position, allocation });
+    }
+    // the page can come back short of limit, or empty, with more still to scan
+    let next_start_after = scanned.filter(|position| position + 1 < indexed);
+
+    Ok(AllocationsResponse { allocations: records, next_start_after })
+}
+
+fn query_cred_id_by_address<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>, address: &CanonicalAddr) -> StdResult<CredIdsResponse> {
+    let cred_ids = address_cred_read(&deps.storage).may_load(address.as_slice())?.unwrap_or_default();
+
+    Ok(CredIdsResponse { cred_ids })
+}
+
+fn query_list_users<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>, start_after: Option<u64>, limit: Option<u32>) -> StdResult<ListUsersResponse> {
+    let state = config_read(&deps.storage).load()?;
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE) as usize;
+    let start = start_after.map(|position| position + 1).unwrap_or(0);
+
+    let mut users = vec![];
+    for position in start..state.users_indexed {
+        if users.len() >= limit {
+            break;
+        }
+        let cred_id = user_index_read(&deps.storage).load(&position.to_be_bytes())?;
+        // deregistered, or registered again further down the index
+        if user_position_read(&deps.storage).may_load(cred_id.as_bytes())? != Some(position) {
+            continue;
+        }
+        let cred = user_cred_read(&deps.storage).load(cred_id.as_bytes())?;
+        users.push(UserInfo {
+            position,
+            cred_id,
+            alias: cred.alias,
+            scrt_address: deps.api.human_address(&cred.scrt_address)?,
+            total_allocated: cred.total_allocated,
+            claimable: cred.claimable,
+            claimed: cred.claimed,
+            token_balances: cred.token_balances,
+        });
+    }
+
+    Ok(ListUsersResponse { users })
+}
+
+fn query_escrow<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>, id: String) -> StdResult<EscrowResponse> {
+    let total_escrowed = escrow_read(&deps.storage).may_load(id.as_bytes())?.unwrap_or_default();
+
+    Ok(EscrowResponse { total_escrowed: total_escrowed.total, tokens: total_escrowed.tokens })
+}
+
+fn query_user_cred<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>, id: String) -> StdResult<UserCredResponse> {
+    let key = &id.as_bytes();
+    let cred = match user_cred_read(&deps.storage).may_load(key)? {
+        Some(cred) => Some(cred),
+        None => return Err(StdError::GenericErr { msg: "User does not exist".to_string(), backtrace: None }),
+    }.unwrap();
+
+    Ok(UserCredResponse {
+        scrt_address: cred.scrt_address,
+        total_allocated: cred.total_allocated,
+        token_balances: cred.token_balances,
+    })
+}
+
+fn query_total_allocated<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>, id: String, token: Option<HumanAddr>) -> StdResult<TotalAllocatedResponse> {
+
+    let key = &id.as_bytes();
+    let cred = match user_cred_read(&deps.storage).may_load(key)? {
+        Some(cred) => Some(cred),
+        None => return Err(StdError::generic_err("User is not registered")),
+    }.unwrap();
+    let token = config_read(&deps.storage).load()?.token_key(token)?;
+
+    Ok(TotalAllocatedResponse { total_allocated: cred.balance(&token).total_allocated })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR};
+    use cosmwasm_std::{coins, from_binary, BankMsg, Decimal, StdError};
+    use crate::state::{PolicyTotal, TokenBalance, CONFIG_KEY, PRNG_SEED_KEY, USER_CRED_KEY};
+    use cosmwasm_storage::{bucket, singleton};
+    use serde::{Deserialize, Serialize};
+    use k256::ecdsa::signature::Signer;
+    use k256::ecdsa::{Signature, SigningKey};
+    pub const TOKEN_HASH: &str = "foocoinhash";
+    
+    const TEST_CREATOR: &str = "creator";
+    const TEST_CREATOR_KEY: &str = "creator_key";
+
+    #[test]
+    fn proper_initialization() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+
+        // it worked, let's query the state
+        let res = query(&deps, QueryMsg::Config {}).unwrap();
+        let state: State = from_binary(&res).unwrap();
+
+        assert_eq!(state, mock_state(&deps));
+    }
+
+    fn mock_token_contract() -> ContractInfo {
+        ContractInfo {
+            code_hash: TOKEN_HASH.to_string(),
+            address: HumanAddr(TOKEN_HASH.to_string()),
+        }
+    }
+
+    // state right after mock_init
+    fn mock_state(deps: &Extern<MockStorage, MockApi, MockQuerier>) -> State {
+        State {
+            total_cred: Uint128::zero(),
+            total_users: 0,
+            users_indexed: 0,
+            total_escrowed: Uint128::zero(),
+            total_unclaimed: Uint128::zero(),
+            total_vesting: Uint128::zero(),
+            token_contract: mock_token_contract(),
+            owner: deps
+                .api
+                .canonical_address(&HumanAddr::from(TEST_CREATOR))
+                .unwrap(),
+            pending_owner: None,
+            registrar_pubkey: None,
+            oracle_pubkey: None,
+            oracle_nonce: 0,
+            reward_tokens: vec![],
+            claim_mode: ClaimMode::Push,
+            payout_mode: PayoutMode::Mint,
+            treasury_balance: Uint128::zero(),
+            contract_address: HumanAddr::from(MOCK_CONTRACT_ADDR),
+            status: ContractStatus::Normal,
+            caps: Caps::default(),
+            balanced_vesting: None,
+            policy_totals: vec![],
+            uniqueness: Uniqueness::default(),
+        }
+    }
+
+    fn mock_init(deps: &mut Extern<MockStorage, MockApi, MockQuerier>) {
+        let msg = InitMsg {
+            token_contract: mock_token_contract(),
+            claim_mode: None,
+            payout_mode: None,
+            caps: None,
+            balanced_vesting: None,
+            uniqueness: None,
+            prng_seed: Binary::from("lolz fun yay".as_bytes()),
+        };
+        let env = mock_env(TEST_CREATOR, &coins(1000, "hush money"));
+
+        let _res = init(deps, env, msg).expect("contract successfully handles InitMsg");
+
+        // the per-user queries in these tests are made as the creator
+        let msg = HandleMsg::SetViewingKey { key: TEST_CREATOR_KEY.to_string(), padding: None };
+        let _res = handle(deps, mock_env(TEST_CREATOR, &[]), msg).unwrap();
+    }
+
+    fn assert_registered(
+        deps: &mut Extern<MockStorage, MockApi, MockQuerier>,
+        cred_id: &str,
+        expected: bool,
+    ) {
+        let res = query(
+            deps,
+            QueryMsg::IsCredRegistered {
+                cred_id: cred_id.to_string(),
+            },
+        ).unwrap();
+
+        let value: CredRegisteredResponse = from_binary(&res).unwrap();
+        assert_eq!(expected, value.registered);
+    }
+
+    fn balanced_total(total: u128) -> Vec<PolicyTotal> {
+        vec![PolicyTotal { policy: "balanced".to_string(), total: Uint128::from(total) }]
+    }
+
+    fn assert_config_state(deps: &Extern<MockStorage, MockApi, MockQuerier>, expected: State) {
+        let res = query(deps, QueryMsg::Config {}).unwrap();
+        let value: State = from_binary(&res).unwrap();
+        assert_eq!(value, expected);
+    }
+
+    fn assert_cred_balance(deps: &Extern<MockStorage, MockApi, MockQuerier>, cred_id: &str, expected: Uint128) {
+        let res = query(deps, QueryMsg::GetUserCred {
+            cred_id: cred_id.to_string(),
+            address: HumanAddr::from(TEST_CREATOR),
+            key: TEST_CREATOR_KEY.to_string(),
+        }).unwrap();
+        let value: UserCredResponse = from_binary(&res).unwrap();
+        assert_eq!(value.total_allocated, expected);
+    }
+
+    fn assert_cred_allocated(deps: &mut Extern<MockStorage, MockApi, MockQuerier>, cred_id: String, allocation_id: String, expected: bool) {
+        let res = query(deps, QueryMsg::IsAllocated {
+            cred_id,
+            allocation_id,
+            address: HumanAddr::from(TEST_CREATOR),
+            key: TEST_CREATOR_KEY.to_string(),
+        }).unwrap();
+        let value: CredAllocatedResponse = from_binary(&res).unwrap();
+        assert_eq!(value.allocated, expected);
+    }
+
+    #[test]
+    fn register_cred_and_query_works() {
+        let mut deps = mock_dependencies(20, &[]);
+        let env = mock_env(TEST_CREATOR, &coins(1000, "hush money"));
+        mock_init(&mut deps);
+
+        let cred_id = "cred1".to_string();
+        let msg = HandleMsg::RegisterUser {
+            cred_id,
+            scrt_address: HumanAddr("secret007".to_string()),
+            alias: Some("secret007".to_string()),
+        };
+
+        let _res = handle(&mut deps, env, msg).expect("contract successfully registers cred");
+        assert_registered(&mut deps, "cred1", true);
+
+        assert_config_state(&deps, State {
+            total_cred: Uint128::zero(),
+            total_users: 1,
+            users_indexed: 1,
+            ..mock_state(&deps)
+        });
+
+        assert_cred_balance(&deps, "cred1", Uint128::zero());
+    }
+
+    #[test]
+    fn allocate_cred_works() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        let env = mock_env(TEST_CREATOR, &coins(100u128, "foocoin"));
+
+        let cred_id = "cred1".to_string();
+        let msg = HandleMsg::RegisterUser {
+            cred_id,
+            scrt_address: HumanAddr("secret007".to_string()),
+            alias: None,
+        };
+
+        let _res = handle(&mut deps, env.clone(), msg).expect("contract successfully registers cred");
+        assert_registered(&mut deps, "cred1", true);
+
+        let cred_id = "cred1".to_string();
+        let msg = HandleMsg::Allocate {
+            allocation_id: "allocation 1".to_string(),
+            policy_type: PolicyType::Balanced,
+            token: None,
+            cred_id,
+            amount: Uint128::from(100u128)
+        };
+
+        let _res = handle(&mut deps, env.clone(), msg).expect("contract successfully allocates cred");
+
+        assert_config_state(&deps, State {
+            total_cred: Uint128::from(100u128),
+            policy_totals: balanced_total(100),
+            total_users: 1,
+            users_indexed: 1,
+            ..mock_state(&deps)
+        });
+
+        assert_cred_balance(&deps, "cred1", Uint128::from(100u128));
+
+        assert_cred_allocated(&mut deps, "cred1".to_string(), "allocation 1".to_string(), true);
+    }
+
+
+    #[test]
+    fn allocate_cred_twice_works() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        let env = mock_env(TEST_CREATOR, &coins(29416857982094508000u128, "foocoin"));
+
+        let cred_id = "cred1".to_string();
+        let msg = HandleMsg::RegisterUser {
+            cred_id,
+            scrt_address: HumanAddr("secret007".to_string()),
+            alias: None,
+        };
+
+        let _res = handle(&mut deps, env.clone(), msg).expect("contract successfully registers cred");
+        assert_registered(&mut deps, "cred1", true);
+
+        let cred_id = "cred1".to_string();
+        let msg = HandleMsg::Allocate {
+            allocation_id: "allocation 1".to_string(),
+            policy_type: PolicyType::Balanced,
+            token: None,
+            cred_id: cred_id.clone(),
+            amount: Uint128::from(14708428991047254000u128)
+        };
+
+        let _res = handle(&mut deps, env.clone(), msg).expect("contract successfully allocates cred");
+
+        assert_config_state(&deps, State {
+            total_cred: Uint128::from(14708428991047254000u128),
+            policy_totals: balanced_total(14708428991047254000),
+            total_users: 1,
+            users_indexed: 1,
+            ..mock_state(&deps)
+        });
+
+        assert_cred_balance(&deps, "cred1", Uint128::from(14708428991047254000u128));
+
+
+        let msg = HandleMsg::Allocate {
+            allocation_id: "allocation 2".to_string(),
+            policy_type: PolicyType::Balanced,
+            token: None,
+            cred_id: cred_id.clone(),
+            amount: Uint128::from(29416857982094508000u128)
+        };
+
+        let handle_res = handle(&mut deps, env.clone(), msg).expect("contract successfully allocates more cred");
+
+        let _msg = handle_res.messages.first().expect("no message");
+
+        assert_config_state(&deps, State {
+            total_cred: Uint128::from(44125286973141762000u128),
+            policy_totals: balanced_total(44125286973141762000),
+            total_users: 1,
+            users_indexed: 1,
+            ..mock_state(&deps)
+        });
+
+        assert_cred_balance(&deps, "cred1", Uint128::from(44125286973141762000u128))
+    }
+
+    #[test]
+    fn allocate_cred_duplicate_fails() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        let env = mock_env(TEST_CREATOR, &coins(100u128, "foocoin"));
+
+        let cred_id = "cred1".to_string();
+        let msg = HandleMsg::RegisterUser {
+            cred_id,
+            scrt_address: HumanAddr("secret007".to_string()),
+            alias: None,
+        };
+
+        let _res = handle(&mut deps, env.clone(), msg).expect("contract successfully registers cred");
+        assert_registered(&mut deps, "cred1", true);
 
         let cred_id = "cred1".to_string();
         let msg = HandleMsg::Allocate {
             allocation_id: "allocation 1".to_string(),
             policy_type: PolicyType::Balanced,
-            cred_id,
+            token: None,
+            cred_id,
+            amount: Uint128::from(100u128)
+        };
+
+        let _res = handle(&mut deps, env.clone(), msg.clone()).expect("contract successfully allocates cred");
+
+        assert_config_state(&deps, State {
+            total_cred: Uint128::from(100u128),
+            policy_totals: balanced_total(100),
+            total_users: 1,
+            users_indexed: 1,
+            ..mock_state(&deps)
+        });
+
+        assert_cred_balance(&deps, "cred1", Uint128::from(100u128));
+
+        let _res = handle(&mut deps, env.clone(), msg);
+
+        match _res {
+            Ok(_) => panic!("expected error"),
+            Err(StdError::GenericErr { msg, .. }) => {
+                assert_eq!(msg, "Already allocated")
+            }
+            Err(e) => panic!("unexpected error: {:?}", e),
+        }
+
+        assert_config_state(&deps, State {
+            total_cred: Uint128::from(100u128),
+            policy_totals: balanced_total(100),
+            total_users: 1,
+            users_indexed: 1,
+            ..mock_state(&deps)
+        });
+
+        assert_cred_balance(&deps, "cred1", Uint128::from(100u128))
+    }
+
+
+    fn mock_register(deps: &mut Extern<MockStorage, MockApi, MockQuerier>, cred_id: &str, scrt_address: &str) {
+        let env = mock_env(TEST_CREATOR, &[]);
+        let msg = HandleMsg::RegisterUser {
+            cred_id: cred_id.to_string(),
+            scrt_address: HumanAddr(scrt_address.to_string()),
+            alias: None,
+        };
+
+        let _res = handle(deps, env, msg).expect("contract successfully registers cred");
+        assert_registered(deps, cred_id, true);
+    }
+
+    #[test]
+    fn allocate_batch_works() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        mock_register(&mut deps, "cred1", "secret007");
+        mock_register(&mut deps, "cred2", "secret008");
+        let env = mock_env(TEST_CREATOR, &[]);
+
+        let msg = HandleMsg::AllocateBatch {
+            allocation_id: "allocation 1".to_string(),
+            policy_type: PolicyType::Balanced,
+            receipts: vec![
+                Receipt { cred_id: "cred1".to_string(), amount: Uint128::from(100u128) },
+                Receipt { cred_id: "cred2".to_string(), amount: Uint128::from(50u128) },
+            ],
+            mode: BatchMode::Reject,
+            token: None,
+        };
+
+        let res = handle(&mut deps, env.clone(), msg).expect("contract successfully allocates batch");
+        assert_eq!(res.messages.len(), 1);
+
+        assert_cred_allocated(&mut deps, "cred1".to_string(), "allocation 1".to_string(), true);
+        assert_cred_allocated(&mut deps, "cred2".to_string(), "allocation 1".to_string(), true);
+
+        let state = config_read(&deps.storage).load().unwrap();
+        assert_eq!(state.total_cred, Uint128::from(150u128));
+
+        // cred1 was already allocated, only cred3 is new
+        mock_register(&mut deps, "cred3", "secret009");
+        let msg = HandleMsg::AllocateBatch {
+            allocation_id: "allocation 1".to_string(),
+            policy_type: PolicyType::Balanced,
+            receipts: vec![
+                Receipt { cred_id: "cred1".to_string(), amount: Uint128::from(100u128) },
+                Receipt { cred_id: "cred3".to_string(), amount: Uint128::from(25u128) },
+            ],
+            mode: BatchMode::Skip,
+            token: None,
+        };
+
+        let res = handle(&mut deps, env, msg).expect("contract successfully skips allocated receipts");
+        assert_eq!(res.messages.len(), 1);
+
+        let state = config_read(&deps.storage).load().unwrap();
+        assert_eq!(state.total_cred, Uint128::from(175u128));
+        assert_cred_allocated(&mut deps, "cred3".to_string(), "allocation 1".to_string(), true);
+
+        // a cred listed twice fails the batch in either mode
+        let msg = HandleMsg::AllocateBatch {
+            allocation_id: "allocation 2".to_string(),
+            policy_type: PolicyType::Balanced,
+            receipts: vec![
+                Receipt { cred_id: "cred1".to_string(), amount: Uint128::from(100u128) },
+                Receipt { cred_id: "cred2".to_string(), amount: Uint128::from(10u128) },
+                Receipt { cred_id: "cred1".to_string(), amount: Uint128::from(20u128) },
+            ],
+            mode: BatchMode::Skip,
+            token: None,
+        };
+        match handle(&mut deps, mock_env(TEST_CREATOR, &[]), msg) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Duplicate receipt: cred1"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_cred_allocated(&mut deps, "cred2".to_string(), "allocation 2".to_string(), false);
+    }
+
+    #[test]
+    fn allocate_batch_reject_is_atomic() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        mock_register(&mut deps, "cred1", "secret007");
+        mock_register(&mut deps, "cred2", "secret008");
+        let env = mock_env(TEST_CREATOR, &[]);
+
+        let msg = HandleMsg::Allocate {
+            allocation_id: "allocation 1".to_string(),
+            policy_type: PolicyType::Balanced,
+            token: None,
+            cred_id: "cred2".to_string(),
+            amount: Uint128::from(50u128)
+        };
+        let _res = handle(&mut deps, env.clone(), msg).expect("contract successfully allocates cred");
+
+        let msg = HandleMsg::AllocateBatch {
+            allocation_id: "allocation 1".to_string(),
+            policy_type: PolicyType::Balanced,
+            receipts: vec![
+                Receipt { cred_id: "cred1".to_string(), amount: Uint128::from(100u128) },
+                Receipt { cred_id: "cred2".to_string(), amount: Uint128::from(50u128) },
+            ],
+            mode: BatchMode::Reject,
+            token: None,
+        };
+
+        match handle(&mut deps, env, msg) {
+            Ok(_) => panic!("expected error"),
+            Err(StdError::GenericErr { msg, .. }) => {
+                assert_eq!(msg, "Already allocated: cred2")
+            }
+            Err(e) => panic!("unexpected error: {:?}", e),
+        }
+
+        // nothing from the rejected batch was applied
+        assert_cred_allocated(&mut deps, "cred1".to_string(), "allocation 1".to_string(), false);
+        let state = config_read(&deps.storage).load().unwrap();
+        assert_eq!(state.total_cred, Uint128::from(50u128));
+    }
+
+    fn assert_escrowed(deps: &Extern<MockStorage, MockApi, MockQuerier>, cred_id: &str, expected: u128) {
+        let res = query(deps, QueryMsg::GetEscrow {
+            cred_id: cred_id.to_string(),
+            address: HumanAddr::from(TEST_CREATOR),
+            key: TEST_CREATOR_KEY.to_string(),
+        }).unwrap();
+        let value: EscrowResponse = from_binary(&res).unwrap();
+        assert_eq!(value.total_escrowed, Uint128::from(expected));
+    }
+
+    #[test]
+    fn allocate_unregistered_escrows_until_registration() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        let env = mock_env(TEST_CREATOR, &[]);
+
+        let msg = HandleMsg::Allocate {
+            allocation_id: "allocation 1".to_string(),
+            policy_type: PolicyType::Balanced,
+            token: None,
+            cred_id: "cred1".to_string(),
+            amount: Uint128::from(100u128)
+        };
+        let res = handle(&mut deps, env.clone(), msg.clone()).expect("contract escrows allocation");
+        assert_eq!(res.messages.len(), 0);
+
+        assert_escrowed(&deps, "cred1", 100);
+        assert_cred_allocated(&mut deps, "cred1".to_string(), "allocation 1".to_string(), true);
+        assert_config_state(&deps, State {
+            total_cred: Uint128::from(100u128),
+            policy_totals: balanced_total(100),
+            total_escrowed: Uint128::from(100u128),
+            ..mock_state(&deps)
+        });
+
+        match handle(&mut deps, env.clone(), msg) {
+            Ok(_) => panic!("expected error"),
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Already allocated"),
+            Err(e) => panic!("unexpected error: {:?}", e),
+        }
+
+        let msg = HandleMsg::RegisterUser {
+            cred_id: "cred1".to_string(),
+            scrt_address: HumanAddr("secret007".to_string()),
+            alias: None,
+        };
+        let res = handle(&mut deps, env, msg).expect("contract successfully registers cred");
+        assert_eq!(res.messages.len(), 1);
+
+        assert_escrowed(&deps, "cred1", 0);
+        assert_cred_allocated(&mut deps, "cred1".to_string(), "allocation 1".to_string(), true);
+        assert_config_state(&deps, State {
+            total_cred: Uint128::from(100u128),
+            policy_totals: balanced_total(100),
+            total_users: 1,
+            users_indexed: 1,
+            ..mock_state(&deps)
+        });
+        assert_cred_balance(&deps, "cred1", Uint128::from(100u128));
+    }
+
+    fn assert_claimable(deps: &Extern<MockStorage, MockApi, MockQuerier>, cred_id: &str, claimable: u128, claimed: u128) {
+        let res = query(deps, QueryMsg::GetClaimable {
+            cred_id: cred_id.to_string(),
+            token: None,
+            address: HumanAddr::from(TEST_CREATOR),
+            key: TEST_CREATOR_KEY.to_string(),
+        }).unwrap();
+        let value: ClaimableResponse = from_binary(&res).unwrap();
+        assert_eq!(value, ClaimableResponse {
+            claimable: Uint128::from(claimable),
+            claimed: Uint128::from(claimed),
+        });
+    }
+
+    #[test]
+    fn claim_mode_defers_minting_until_claimed() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        mock_register(&mut deps, "cred1", "secret007");
+        let env = mock_env(TEST_CREATOR, &[]);
+
+        let msg = HandleMsg::SetClaimMode { claim_mode: ClaimMode::Claim };
+        let _res = handle(&mut deps, env.clone(), msg).expect("owner sets claim mode");
+
+        let msg = HandleMsg::Allocate {
+            allocation_id: "allocation 1".to_string(),
+            policy_type: PolicyType::Balanced,
+            token: None,
+            cred_id: "cred1".to_string(),
+            amount: Uint128::from(100u128)
+        };
+        let res = handle(&mut deps, env.clone(), msg).expect("contract successfully allocates cred");
+        assert_eq!(res.messages.len(), 0);
+        assert_claimable(&deps, "cred1", 100, 0);
+        assert_cred_balance(&deps, "cred1", Uint128::from(100u128));
+
+        // only the registered address can claim
+        let res = handle(&mut deps, env, HandleMsg::Claim { amount: None, token: None, cred_id: None });
+        match res {
+            Ok(_) => panic!("expected error"),
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "User not registered"),
+            Err(e) => panic!("unexpected error: {:?}", e),
+        }
+
+        let user_env = mock_env("secret007", &[]);
+        let res = handle(&mut deps, user_env.clone(), HandleMsg::Claim { amount: Some(Uint128::from(150u128)), token: None, cred_id: None });
+        match res {
+            Ok(_) => panic!("expected error"),
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Insufficient claimable balance"),
+            Err(e) => panic!("unexpected error: {:?}", e),
+        }
+
+        let res = handle(&mut deps, user_env.clone(), HandleMsg::Claim { amount: Some(Uint128::from(40u128)), token: None, cred_id: None })
+            .expect("user claims part of the balance");
+        assert_eq!(res.messages.len(), 1);
+        assert_claimable(&deps, "cred1", 60, 40);
+
+        let _res = handle(&mut deps, user_env, HandleMsg::Claim { amount: None, token: None, cred_id: None })
+            .expect("user claims the rest");
+        assert_claimable(&deps, "cred1", 0, 100);
+
+        let state = config_read(&deps.storage).load().unwrap();
+        assert_eq!(state.total_unclaimed, Uint128::zero());
+    }
+
+    // State and UserCred as the first release stored them
+    #[derive(Serialize, Deserialize)]
+    struct FirstReleaseState {
+        total_cred: Uint128,
+        total_users: u64,
+        owner: CanonicalAddr,
+        token_contract: ContractInfo,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct FirstReleaseUserCred {
+        cred_id: String,
+        scrt_address: CanonicalAddr,
+        total_allocated: Uint128,
+        allocations: Vec<Allocation>,
+        alias: Option<String>,
+    }
+
+    #[test]
+    fn migrate_legacy_allocations() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        let env = mock_env(TEST_CREATOR, &[]);
+
+        let state = FirstReleaseState {
+            total_cred: Uint128::from(100u128),
+            total_users: 1,
+            owner: deps.api.canonical_address(&HumanAddr::from(TEST_CREATOR)).unwrap(),
+            token_contract: mock_token_contract(),
+        };
+        singleton(&mut deps.storage, CONFIG_KEY).save(&state).unwrap();
+        deps.storage.remove(PRNG_SEED_KEY);
+
+        // a record saved with the allocations inline
+        let legacy = FirstReleaseUserCred {
+            cred_id: "cred1".to_string(),
+            scrt_address: deps.api.canonical_address(&HumanAddr::from("secret007")).unwrap(),
+            total_allocated: Uint128::from(100u128),
+            allocations: vec![Allocation {
+                policy: PolicyType::Balanced,
+                amount: Uint128::from(100u128),
+                allocation_id: "allocation 1".to_string(),
+                token: None,
+                in_distribution: false,
+            }],
+            alias: Some("levackt".to_string()),
+        };
+        bucket(USER_CRED_KEY, &mut deps.storage).save(b"cred1", &legacy).unwrap();
+        assert_cred_allocated(&mut deps, "cred1".to_string(), "allocation 1".to_string(), false);
+
+        // changing the record before it is migrated would drop its allocations
+        let update = HandleMsg::UpdateUserAddress { cred_id: "cred1".to_string(), scrt_address: HumanAddr::from("secret009") };
+        match handle(&mut deps, env.clone(), update) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Allocations of cred1 must be migrated first"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let msg = HandleMsg::MigrateAllocations { cred_ids: vec!["cred1".to_string()] };
+        let _res = handle(&mut deps, env.clone(), msg.clone()).expect("owner migrates allocations");
+        assert_cred_allocated(&mut deps, "cred1".to_string(), "allocation 1".to_string(), true);
+        assert_cred_balance(&deps, "cred1", Uint128::from(100u128));
+
+        let state = config_read(&deps.storage).load().unwrap();
+        assert_eq!(state.total_cred, Uint128::from(100u128));
+        assert_eq!(state.users_indexed, 1);
+        assert_eq!(state.contract_address, HumanAddr::from(MOCK_CONTRACT_ADDR));
+        let create_key = HandleMsg::CreateViewingKey { entropy: "entropy".to_string(), padding: None };
+        let _res = handle(&mut deps, mock_env("secret007", &[]), create_key).expect("viewing keys work after migrating");
+
+        let cred = user_cred_read(&deps.storage).load(b"cred1").unwrap();
+        assert!(cred.allocations.is_empty());
+        let allocations = query_allocations(&deps, "cred1".to_string(), None, None, None).unwrap().allocations;
+        assert_eq!(allocations.len(), 1);
+        assert_eq!(allocations[0].allocation.allocation_id, "allocation 1");
+
+        // the address and alias lookups are backfilled
+        let address = deps.api.canonical_address(&HumanAddr::from("secret007")).unwrap();
+        assert_eq!(address_cred_read(&deps.storage).load(address.as_slice()).unwrap(), vec!["cred1".to_string()]);
+        let res = query(&deps, QueryMsg::CredIdByAlias { alias: "levackt".to_string() }).unwrap();
+        assert_eq!(from_binary::<CredIdsResponse>(&res).unwrap().cred_ids, vec!["cred1".to_string()]);
+
+        // repeating is a no-op
+        let _res = handle(&mut deps, env.clone(), msg).expect("migration can be repeated");
+
+        // users registering afterwards are listed after the migrated one
+        mock_register(&mut deps, "cred2", "secret008");
+        let res = query(&deps, QueryMsg::ListUsers {
+            start_after: None,
+            limit: None,
+            address: HumanAddr::from(TEST_CREATOR),
+            key: TEST_CREATOR_KEY.to_string(),
+        }).unwrap();
+        let users = from_binary::<ListUsersResponse>(&res).unwrap().users;
+        assert_eq!(users.iter().map(|user| (user.position, user.cred_id.as_str())).collect::<Vec<_>>(), vec![(0, "cred1"), (1, "cred2")]);
+
+        let msg = HandleMsg::Allocate {
+            allocation_id: "allocation 1".to_string(),
+            policy_type: PolicyType::Balanced,
+            token: None,
+            cred_id: "cred1".to_string(),
+            amount: Uint128::from(100u128)
+        };
+        match handle(&mut deps, env.clone(), msg) {
+            Ok(_) => panic!("expected error"),
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Already allocated"),
+            Err(e) => panic!("unexpected error: {:?}", e),
+        }
+
+        // a distribution reusing the id doesn't count the migrated allocation,
+        // so revoking it leaves the distribution alone
+        let _res = handle(&mut deps, env.clone(), HandleMsg::CreateDistribution {
+            id: "allocation 1".to_string(),
+            policy: PolicyType::Balanced,
+            budget: Uint128(500),
+            timestamp: 1000,
+            token: None,
+        }).unwrap();
+        let _res = handle(&mut deps, env, HandleMsg::RevokeAllocation {
+            cred_id: "cred1".to_string(),
+            allocation_id: "allocation 1".to_string(),
+            reason: "duplicate".to_string(),
+        }).unwrap();
+        let res = query(&deps, QueryMsg::Distribution { id: "allocation 1".to_string() }).unwrap();
+        let value: DistributionResponse = from_binary(&res).unwrap();
+        assert_eq!(value.distribution.allocated, Uint128::zero());
+        assert_eq!(value.distribution.recipients, 0);
+    }
+
+    fn assert_pending_owner(deps: &Extern<MockStorage, MockApi, MockQuerier>, expected: Option<&str>) {
+        let res = query(deps, QueryMsg::PendingOwner {}).unwrap();
+        let value: PendingOwnerResponse = from_binary(&res).unwrap();
+        assert_eq!(value.pending_owner, expected.map(HumanAddr::from));
+    }
+
+    #[test]
+    fn ownership_transfer_works() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        let env = mock_env(TEST_CREATOR, &[]);
+        let new_owner_env = mock_env("new_owner", &[]);
+
+        // only the owner proposes
+        let msg = HandleMsg::ProposeOwner { address: HumanAddr::from("new_owner") };
+        match handle(&mut deps, new_owner_env.clone(), msg.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let _res = handle(&mut deps, env.clone(), msg).expect("owner proposes a new owner");
+        assert_pending_owner(&deps, Some("new_owner"));
+
+        // only the proposed owner accepts
+        match handle(&mut deps, mock_env("someone", &[]), HandleMsg::AcceptOwnership {}) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let _res = handle(&mut deps, new_owner_env.clone(), HandleMsg::AcceptOwnership {})
+            .expect("proposed owner accepts");
+        assert_pending_owner(&deps, None);
+
+        let state = config_read(&deps.storage).load().unwrap();
+        assert_eq!(state.owner, deps.api.canonical_address(&HumanAddr::from("new_owner")).unwrap());
+
+        // the previous owner lost admin rights
+        let msg = HandleMsg::ProposeOwner { address: HumanAddr::from(TEST_CREATOR) };
+        match handle(&mut deps, env, msg) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn cancel_ownership_proposal_works() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        let env = mock_env(TEST_CREATOR, &[]);
+
+        let msg = HandleMsg::ProposeOwner { address: HumanAddr::from("new_owner") };
+        let _res = handle(&mut deps, env.clone(), msg).expect("owner proposes a new owner");
+
+        let _res = handle(&mut deps, env.clone(), HandleMsg::CancelOwnershipProposal {})
+            .expect("owner cancels the proposal");
+        assert_pending_owner(&deps, None);
+
+        match handle(&mut deps, mock_env("new_owner", &[]), HandleMsg::AcceptOwnership {}) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    fn assert_role_holders(deps: &Extern<MockStorage, MockApi, MockQuerier>, role: Role, expected: Vec<&str>) {
+        let res = query(deps, QueryMsg::RoleHolders { role }).unwrap();
+        let value: RoleHoldersResponse = from_binary(&res).unwrap();
+        assert_eq!(value.holders, expected.into_iter().map(HumanAddr::from).collect::<Vec<HumanAddr>>());
+    }
+
+    #[test]
+    fn allocator_role_works() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        mock_register(&mut deps, "cred1", "secret007");
+        let env = mock_env(TEST_CREATOR, &[]);
+        let oracle_env = mock_env("oracle", &[]);
+
+        let allocate = HandleMsg::Allocate {
+            allocation_id: "allocation 1".to_string(),
+            policy_type: PolicyType::Balanced,
+            token: None,
+            cred_id: "cred1".to_string(),
+            amount: Uint128::from(100u128)
+        };
+        match handle(&mut deps, oracle_env.clone(), allocate.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let msg = HandleMsg::GrantRole { role: Role::Allocator, address: HumanAddr::from("oracle") };
+        match handle(&mut deps, oracle_env.clone(), msg.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        let _res = handle(&mut deps, env.clone(), msg).expect("owner grants allocator");
+        assert_role_holders(&deps, Role::Allocator, vec!["oracle"]);
+        assert_role_holders(&deps, Role::Owner, vec![TEST_CREATOR]);
+
+        let _res = handle(&mut deps, oracle_env.clone(), allocate).expect("allocator allocates");
+
+        // allocating doesn't let the oracle register users
+        let msg = HandleMsg::RegisterUser {
+            cred_id: "cred2".to_string(),
+            scrt_address: HumanAddr("secret008".to_string()),
+            alias: None,
+        };
+        match handle(&mut deps, oracle_env.clone(), msg) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let msg = HandleMsg::RevokeRole { role: Role::Allocator, address: HumanAddr::from("oracle") };
+        let _res = handle(&mut deps, env.clone(), msg).expect("owner revokes allocator");
+        assert_role_holders(&deps, Role::Allocator, vec![]);
+
+        let allocate = HandleMsg::Allocate {
+            allocation_id: "allocation 2".to_string(),
+            policy_type: PolicyType::Balanced,
+            token: None,
+            cred_id: "cred1".to_string(),
+            amount: Uint128::from(100u128)
+        };
+        match handle(&mut deps, oracle_env, allocate) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let msg = HandleMsg::GrantRole { role: Role::Owner, address: HumanAddr::from("oracle") };
+        match handle(&mut deps, env, msg) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Ownership is transferred with propose_owner"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn update_user_address_works() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        mock_register(&mut deps, "cred1", "secret007");
+
+        // neither a registrar nor the user
+        let msg = HandleMsg::UpdateUserAddress {
+            cred_id: "cred1".to_string(),
+            scrt_address: HumanAddr::from("secret008"),
+        };
+        match handle(&mut deps, mock_env("someone", &[]), msg.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let res = handle(&mut deps, mock_env("secret007", &[]), msg).expect("user updates their address");
+        assert!(res.log.contains(&log("new_address", "secret008")));
+
+        let res = query(&deps, QueryMsg::GetUserCred {
+            cred_id: "cred1".to_string(),
+            address: HumanAddr::from(TEST_CREATOR),
+            key: TEST_CREATOR_KEY.to_string(),
+        }).unwrap();
+        let value: UserCredResponse = from_binary(&res).unwrap();
+        assert_eq!(value.scrt_address, deps.api.canonical_address(&HumanAddr::from("secret008")).unwrap());
+
+        // the old address no longer controls the cred_id
+        let msg = HandleMsg::UpdateUserAddress {
+            cred_id: "cred1".to_string(),
+            scrt_address: HumanAddr::from("secret007"),
+        };
+        match handle(&mut deps, mock_env("secret007", &[]), msg.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), msg).expect("registrar updates the address");
+
+        let state = config_read(&deps.storage).load().unwrap();
+        assert_eq!(state.total_users, 1);
+    }
+
+    #[test]
+    fn deregister_user_keeps_allocations() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        mock_register(&mut deps, "cred1", "secret007");
+        let env = mock_env(TEST_CREATOR, &[]);
+
+        let _res = handle(&mut deps, env.clone(), HandleMsg::SetClaimMode { claim_mode: ClaimMode::Claim }).unwrap();
+        let msg = HandleMsg::Allocate {
+            allocation_id: "allocation 1".to_string(),
+            policy_type: PolicyType::Balanced,
+            token: None,
+            cred_id: "cred1".to_string(),
             amount: Uint128::from(100u128)
         };
+        let _res = handle(&mut deps, env.clone(), msg).expect("contract successfully allocates cred");
+        let msg = HandleMsg::Claim { amount: Some(Uint128::from(40u128)), token: None, cred_id: None };
+        let _res = handle(&mut deps, mock_env("secret007", &[]), msg).expect("user claims part of the allocation");
+
+        let msg = HandleMsg::DeregisterUser { cred_id: "cred1".to_string() };
+        let res = handle(&mut deps, env.clone(), msg).expect("registrar deregisters the user");
+        assert!(res.log.contains(&log("action", "deregister-user")));
+
+        assert_registered(&mut deps, "cred1", false);
+        assert_cred_allocated(&mut deps, "cred1".to_string(), "allocation 1".to_string(), true);
+        assert_escrowed(&deps, "cred1", 60);
+        assert_config_state(&deps, State {
+            total_cred: Uint128::from(100u128),
+            policy_totals: balanced_total(100),
+            total_escrowed: Uint128::from(60u128),
+            users_indexed: 1,
+            claim_mode: ClaimMode::Claim,
+            ..mock_state(&deps)
+        });
+
+        // the old address can't claim anymore
+        match handle(&mut deps, mock_env("secret007", &[]), HandleMsg::Claim { amount: None, token: None, cred_id: None }) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "User not registered"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // registering again brings the unclaimed balance and the totals back
+        mock_register(&mut deps, "cred1", "secret008");
+        assert_claimable(&deps, "cred1", 60, 40);
+        assert_cred_balance(&deps, "cred1", Uint128::from(100u128));
+        let state = config_read(&deps.storage).load().unwrap();
+        assert_eq!(state.total_users, 1);
+    }
+
+    fn sign_attestation(signing_key: &SigningKey, cred_id: &str, scrt_address: &str, registrations: u64, expiry: u64) -> Binary {
+        let attestation = IdentityAttestation {
+            contract_address: HumanAddr::from(MOCK_CONTRACT_ADDR),
+            cred_id: cred_id.to_string(),
+            scrt_address: HumanAddr::from(scrt_address),
+            registrations,
+            expiry,
+        };
+        let signature: Signature = signing_key.sign(&to_vec(&attestation).unwrap());
+        Binary(signature.as_ref().to_vec())
+    }
+
+    #[test]
+    fn claim_identity_works() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        let user_env = mock_env("secret007", &[]);
+        let expiry = user_env.block.time + 3600;
+
+        let registrar_key = SigningKey::from_bytes(&[7u8; 32]).unwrap();
+        let signature = sign_attestation(&registrar_key, "cred1", "secret007", 0, expiry);
+        let msg = HandleMsg::ClaimIdentity {
+            cred_id: "cred1".to_string(),
+            alias: Some("secret007".to_string()),
+            expiry,
+            signature: signature.clone(),
+        };
+
+        match handle(&mut deps, user_env.clone(), msg.clone()) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Self registration is not enabled"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let pubkey = Binary(registrar_key.verify_key().to_bytes().to_vec());
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), HandleMsg::SetRegistrarKey { pubkey: Some(pubkey) })
+            .expect("owner sets the registrar key");
+
+        // attested for a different address
+        match handle(&mut deps, mock_env("secret008", &[]), msg.clone()) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Invalid attestation"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let expired = HandleMsg::ClaimIdentity {
+            cred_id: "cred1".to_string(),
+            alias: None,
+            expiry: user_env.block.time - 1,
+            signature: sign_attestation(&registrar_key, "cred1", "secret007", 0, user_env.block.time - 1),
+        };
+        match handle(&mut deps, user_env.clone(), expired) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Attestation expired"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // attested for another deployment
+        let attestation = IdentityAttestation {
+            contract_address: HumanAddr::from("other_contract"),
+            cred_id: "cred1".to_string(),
+            scrt_address: HumanAddr::from("secret007"),
+            registrations: 0,
+            expiry,
+        };
+        let signature: Signature = registrar_key.sign(&to_vec(&attestation).unwrap());
+        let other_contract = HandleMsg::ClaimIdentity {
+            cred_id: "cred1".to_string(),
+            alias: None,
+            expiry,
+            signature: Binary(signature.as_ref().to_vec()),
+        };
+        match handle(&mut deps, user_env.clone(), other_contract) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Invalid attestation"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let _res = handle(&mut deps, user_env.clone(), msg.clone()).expect("user claims their identity");
+        assert_registered(&mut deps, "cred1", true);
+
+        // the attestation was for the first registration only, including after deregistering
+        match handle(&mut deps, user_env.clone(), msg.clone()) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Invalid attestation"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        let deregister = HandleMsg::DeregisterUser { cred_id: "cred1".to_string() };
+        let _res = handle(&mut deps, user_env.clone(), deregister).unwrap();
+        match handle(&mut deps, user_env.clone(), msg) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Invalid attestation"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        let res = query(&deps, QueryMsg::IsCredRegistered { cred_id: "cred1".to_string() }).unwrap();
+        let value: CredRegisteredResponse = from_binary(&res).unwrap();
+        assert_eq!(value, CredRegisteredResponse { registered: false, registrations: 1 });
+        let _res = handle(&mut deps, user_env, HandleMsg::ClaimIdentity {
+            cred_id: "cred1".to_string(),
+            alias: None,
+            expiry,
+            signature: sign_attestation(&registrar_key, "cred1", "secret007", 1, expiry),
+        }).expect("user registers again with a new attestation");
+    }
+
+    #[test]
+    fn register_twice_fails() {
+        let mut deps = mock_dependencies(20, &[]);
+        let env = mock_env(TEST_CREATOR, &coins(1000, "hush money"));
+        mock_init(&mut deps);
+
+        let cred_id = "cred1".to_string();
+        let msg = HandleMsg::RegisterUser {
+            cred_id,
+            scrt_address: HumanAddr("secret007".to_string()),
+            alias: None,
+        };
+
+        let _res = handle(&mut deps, env.clone(), msg.clone()).expect("contract successfully registers cred");
+        assert_registered(&mut deps, "cred1", true);
+        let _res = handle(&mut deps, env, msg);
+        match _res {
+            Ok(_) => panic!("expected error"),
+            Err(StdError::GenericErr { msg, .. }) => {
+                assert_eq!(msg, "User already exists")
+            }
+            Err(e) => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn viewing_key_gates_user_queries() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        mock_register(&mut deps, "cred1", "secret007");
+        mock_register(&mut deps, "cred2", "secret008");
+
+        let msg = HandleMsg::CreateViewingKey { entropy: "some entropy".to_string(), padding: None };
+        let res = handle(&mut deps, mock_env("secret007", &[]), msg).unwrap();
+        let key = match from_binary(&res.data.unwrap()).unwrap() {
+            HandleAnswer::CreateViewingKey { key } => key,
+        };
+
+        let query_as = |deps: &Extern<MockStorage, MockApi, MockQuerier>, cred_id: &str, address: &str, key: &str| {
+            query(deps, QueryMsg::GetTotalAllocated {
+                cred_id: cred_id.to_string(),
+                token: None,
+                address: HumanAddr::from(address),
+                key: key.to_string(),
+            })
+        };
+
+        let res = query_as(&deps, "cred1", "secret007", &key.0).unwrap();
+        let value: TotalAllocatedResponse = from_binary(&res).unwrap();
+        assert_eq!(value.total_allocated, Uint128::zero());
+
+        match query_as(&deps, "cred1", "secret007", "wrong key") {
+            Err(StdError::GenericErr { msg, .. }) => {
+                assert_eq!(msg, "Wrong viewing key for this address or viewing key not set")
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // no key set for secret008 yet
+        match query_as(&deps, "cred2", "secret008", &key.0) {
+            Err(StdError::GenericErr { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // a valid key only reads the cred registered to its address
+        match query_as(&deps, "cred2", "secret007", &key.0) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // the registrar reads every cred
+        let _res = query_as(&deps, "cred2", TEST_CREATOR, TEST_CREATOR_KEY).unwrap();
+
+        // public queries have no key to check
+        match viewing_keys_queries(&deps, QueryMsg::Config {}) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "This query type does not require authentication"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    fn sign_permit(signing_key: &SigningKey, permit_name: &str, permissions: Vec<Permission>) -> Permit {
+        let params = permit::PermitParams {
+            allowed_tokens: vec![HumanAddr::from(MOCK_CONTRACT_ADDR)],
+            permit_name: permit_name.to_string(),
+            chain_id: "secret-2".to_string(),
+            permissions,
+        };
+        let signature: Signature = signing_key.sign(&to_vec(&permit::SignedPermit::from_params(&params)).unwrap());
+
+        Permit {
+            params,
+            signature: permit::PermitSignature {
+                pub_key: permit::PubKey {
+                    r#type: "tendermint/PubKeySecp256k1".to_string(),
+                    value: Binary(signing_key.verify_key().to_bytes().to_vec()),
+                },
+                signature: Binary(signature.as_ref().to_vec()),
+            },
+        }
+    }
+
+    #[test]
+    fn permit_queries_work() {
+        // bech32 addresses are longer than the default mock canonical length
+        let mut deps = mock_dependencies(45, &[]);
+        mock_init(&mut deps);
+
+        let signing_key = SigningKey::from_bytes(&[7u8; 32]).unwrap();
+        let address = permit::pubkey_to_address(&signing_key.verify_key().to_bytes()).unwrap();
+        mock_register(&mut deps, "cred1", address.as_str());
+        mock_register(&mut deps, "cred2", "secret008");
+
+        let permit = sign_permit(&signing_key, "wallet", vec![Permission::Cred]);
+        let query_with = |deps: &Extern<MockStorage, MockApi, MockQuerier>, permit: &Permit, query: QueryWithPermit| {
+            super::query(deps, QueryMsg::WithPermit { permit: permit.clone(), query })
+        };
+
+        let res = query_with(&deps, &permit, QueryWithPermit::GetTotalAllocated { cred_id: "cred1".to_string(), token: None }).unwrap();
+        let value: TotalAllocatedResponse = from_binary(&res).unwrap();
+        assert_eq!(value.total_allocated, Uint128::zero());
+
+        // only the signer's cred
+        match query_with(&deps, &permit, QueryWithPermit::GetTotalAllocated { cred_id: "cred2".to_string(), token: None }) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // allocations need their own permission
+        let query = QueryWithPermit::IsAllocated { cred_id: "cred1".to_string(), allocation_id: "alloc1".to_string() };
+        match query_with(&deps, &permit, query.clone()) {
+            Err(StdError::GenericErr { msg, .. }) => assert!(msg.starts_with("No permission to query")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        let owner_permit = sign_permit(&signing_key, "owner", vec![Permission::Owner]);
+        let _res = query_with(&deps, &owner_permit, query).unwrap();
+
+        // tampered params no longer match the signature
+        let mut tampered = permit.clone();
+        tampered.params.permissions = vec![Permission::Owner];
+        match query_with(&deps, &tampered, QueryWithPermit::GetUserCred { cred_id: "cred1".to_string() }) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Failed to verify signatures for the given permit"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // permits for other contracts are rejected
+        let mut other_contract = permit.clone();
+        other_contract.params.allowed_tokens = vec![HumanAddr::from("other")];
+        match query_with(&deps, &other_contract, QueryWithPermit::GetUserCred { cred_id: "cred1".to_string() }) {
+            Err(StdError::GenericErr { msg, .. }) => assert!(msg.starts_with("Permit doesn't apply to contract")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let msg = HandleMsg::RevokePermit { permit_name: "wallet".to_string(), padding: None };
+        let _res = handle(&mut deps, mock_env(address.as_str(), &[]), msg).unwrap();
+        match query_with(&deps, &permit, QueryWithPermit::GetUserCred { cred_id: "cred1".to_string() }) {
+            Err(StdError::GenericErr { msg, .. }) => assert!(msg.starts_with("Permit \"wallet\" was revoked")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn transfer_mode_pays_from_treasury() {
+        let mut deps = mock_dependencies(20, &[]);
+        let msg = InitMsg {
+            token_contract: mock_token_contract(),
+            claim_mode: None,
+            payout_mode: Some(PayoutMode::Transfer),
+            caps: None,
+            balanced_vesting: None,
+            uniqueness: None,
+            prng_seed: Binary::from("lolz fun yay".as_bytes()),
+        };
+        let res = init(&mut deps, mock_env(TEST_CREATOR, &[]), msg).unwrap();
+        assert_eq!(res.messages, vec![snip20::HandleMsg::RegisterReceive {
+            code_hash: "".to_string(),
+            padding: None,
+        }.to_cosmos_msg(BLOCK_SIZE, &mock_token_contract()).unwrap()]);
+        mock_register(&mut deps, "cred1", "secret007");
+
+        let allocate = |cred_id: &str, allocation_id: &str, amount: u128| HandleMsg::Allocate {
+            cred_id: cred_id.to_string(),
+            allocation_id: allocation_id.to_string(),
+            amount: Uint128(amount),
+            policy_type: PolicyType::Balanced,
+            token: None,
+        };
+        match handle(&mut deps, mock_env(TEST_CREATOR, &[]), allocate("cred1", "alloc1", 100)) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Insufficient treasury balance: 0 available"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let receive = HandleMsg::Receive {
+            sender: HumanAddr::from(TEST_CREATOR),
+            from: HumanAddr::from(TEST_CREATOR),
+            amount: Uint128(150),
+            memo: None,
+            msg: None,
+        };
+        // only the token reports deposits
+        match handle(&mut deps, mock_env(TEST_CREATOR, &[]), receive.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        let _res = handle(&mut deps, mock_env(TOKEN_HASH, &[]), receive).unwrap();
+
+        let res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), allocate("cred1", "alloc1", 100)).unwrap();
+        assert_eq!(res.messages, vec![snip20::HandleMsg::Transfer {
+            recipient: HumanAddr::from("secret007"),
+            amount: Uint128(100),
+            memo: Some("alloc1".to_string()),
+            padding: None,
+        }.to_cosmos_msg(BLOCK_SIZE, &mock_token_contract()).unwrap()]);
+
+        // escrow is owed from the treasury as well
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), allocate("cred2", "alloc1", 50)).unwrap();
+        match handle(&mut deps, mock_env(TEST_CREATOR, &[]), allocate("cred3", "alloc1", 1)) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Insufficient treasury balance: 0 available"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let res = query(&deps, QueryMsg::Treasury { token: None }).unwrap();
+        let value: TreasuryResponse = from_binary(&res).unwrap();
+        assert_eq!(value, TreasuryResponse {
+            payout_mode: PayoutMode::Transfer,
+            balance: Uint128(50),
+            liabilities: Uint128(50),
+            native_balance: None,
+        });
+    }
+
+    #[test]
+    fn native_mode_pays_with_bank_sends() {
+        let mut deps = mock_dependencies(20, &[]);
+        let msg = InitMsg {
+            token_contract: mock_token_contract(),
+            claim_mode: None,
+            payout_mode: Some(PayoutMode::Native),
+            caps: None,
+            balanced_vesting: None,
+            uniqueness: None,
+            prng_seed: Binary::from("lolz fun yay".as_bytes()),
+        };
+        let res = init(&mut deps, mock_env(TEST_CREATOR, &[]), msg).unwrap();
+        assert!(res.messages.is_empty());
+        mock_register(&mut deps, "cred1", "secret007");
+        mock_register(&mut deps, "cred2", "secret008");
+
+        let fund = HandleMsg::Fund { amount: Uint128(300) };
+        match handle(&mut deps, mock_env(TEST_CREATOR, &coins(200, NATIVE_DENOM)), fund.clone()) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Insufficient funds sent"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match handle(&mut deps, mock_env(TEST_CREATOR, &coins(400, NATIVE_DENOM)), fund.clone()) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Sent more funds than the amount"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match handle(&mut deps, mock_env("someone", &coins(300, NATIVE_DENOM)), fund.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &coins(300, NATIVE_DENOM)), fund).unwrap();
+        deps.querier.update_balance(MOCK_CONTRACT_ADDR, coins(300, NATIVE_DENOM));
+
+        let msg = HandleMsg::AllocateBatch {
+            allocation_id: "alloc1".to_string(),
+            policy_type: PolicyType::Balanced,
+            receipts: vec![
+                Receipt { cred_id: "cred1".to_string(), amount: Uint128(100) },
+                Receipt { cred_id: "cred2".to_string(), amount: Uint128(50) },
+            ],
+            mode: BatchMode::Reject,
+            token: None,
+        };
+        let res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), msg).unwrap();
+        assert_eq!(res.messages, vec![
+            CosmosMsg::Bank(BankMsg::Send {
+                from_address: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                to_address: HumanAddr::from("secret007"),
+                amount: coins(100, NATIVE_DENOM),
+            }),
+            CosmosMsg::Bank(BankMsg::Send {
+                from_address: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                to_address: HumanAddr::from("secret008"),
+                amount: coins(50, NATIVE_DENOM),
+            }),
+        ]);
+
+        let msg = HandleMsg::Allocate {
+            cred_id: "cred3".to_string(),
+            allocation_id: "alloc1".to_string(),
+            amount: Uint128(151),
+            policy_type: PolicyType::Balanced,
+            token: None,
+        };
+        match handle(&mut deps, mock_env(TEST_CREATOR, &[]), msg) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Insufficient treasury balance: 150 available"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let res = query(&deps, QueryMsg::Treasury { token: None }).unwrap();
+        let value: TreasuryResponse = from_binary(&res).unwrap();
+        assert_eq!(value, TreasuryResponse {
+            payout_mode: PayoutMode::Native,
+            balance: Uint128(150),
+            liabilities: Uint128::zero(),
+            native_balance: Some(Uint128(300)),
+        });
+    }
+
+    #[test]
+    fn allocations_pay_in_reward_tokens() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        mock_register(&mut deps, "cred1", "secret007");
+
+        let stable = ContractInfo {
+            code_hash: "stablehash".to_string(),
+            address: HumanAddr::from("stable"),
+        };
+        let msg = HandleMsg::AddRewardToken { token: stable.clone() };
+        match handle(&mut deps, mock_env("someone", &[]), msg.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), msg.clone()).unwrap();
+        match handle(&mut deps, mock_env(TEST_CREATOR, &[]), msg) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Reward token already registered"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let allocate = |cred_id: &str, amount: u128, token: Option<&str>| HandleMsg::Allocate {
+            cred_id: cred_id.to_string(),
+            allocation_id: format!("alloc-{}", token.unwrap_or("default")),
+            amount: Uint128(amount),
+            policy_type: PolicyType::Balanced,
+            token: token.map(HumanAddr::from),
+        };
+        match handle(&mut deps, mock_env(TEST_CREATOR, &[]), allocate("cred1", 10, Some("unknown"))) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Unknown reward token: unknown"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), allocate("cred1", 10, Some("stable"))).unwrap();
+        assert_eq!(res.messages, vec![snip20::HandleMsg::Mint {
+            recipient: HumanAddr::from("secret007"),
+            amount: Uint128(10),
+            memo: Some("alloc-stable".to_string()),
+            padding: None,
+        }.to_cosmos_msg(BLOCK_SIZE, &stable).unwrap()]);
+
+        // escrow is released in every token on registration
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), allocate("cred2", 100, None)).unwrap();
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), allocate("cred2", 20, Some("stable"))).unwrap();
+        let msg = HandleMsg::RegisterUser {
+            cred_id: "cred2".to_string(),
+            scrt_address: HumanAddr::from("secret008"),
+            alias: None,
+        };
+        let res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(res.messages[1], snip20::HandleMsg::Mint {
+            recipient: HumanAddr::from("secret008"),
+            amount: Uint128(20),
+            memo: None,
+            padding: None,
+        }.to_cosmos_msg(BLOCK_SIZE, &stable).unwrap());
+        assert_eq!(res.log, vec![
+            log("action", "release-escrow"),
+            log("cred_id", "cred2"),
+            log("token", mock_token_contract().address.as_str()),
+            log("amount", "100"),
+            log("token", "stable"),
+            log("amount", "20"),
+        ]);
+
+        let res = query(&deps, QueryMsg::GetUserCred {
+            cred_id: "cred2".to_string(),
+            address: HumanAddr::from(TEST_CREATOR),
+            key: TEST_CREATOR_KEY.to_string(),
+        }).unwrap();
+        let value: UserCredResponse = from_binary(&res).unwrap();
+        assert_eq!(value.total_allocated, Uint128(100));
+        assert_eq!(value.token_balances, vec![TokenBalance {
+            token: Some(HumanAddr::from("stable")),
+            total_allocated: Uint128(20),
+            claimable: Uint128::zero(),
+            claimed: Uint128::zero(),
+            debt: Uint128::zero(),
+        }]);
+
+        let res = query(&deps, QueryMsg::GetTotalAllocated {
+            cred_id: "cred2".to_string(),
+            token: Some(HumanAddr::from("stable")),
+            address: HumanAddr::from(TEST_CREATOR),
+            key: TEST_CREATOR_KEY.to_string(),
+        }).unwrap();
+        let value: TotalAllocatedResponse = from_binary(&res).unwrap();
+        assert_eq!(value.total_allocated, Uint128(20));
+        let res = query(&deps, QueryMsg::GetClaimable {
+            cred_id: "cred2".to_string(),
+            token: Some(HumanAddr::from("stable")),
+            address: HumanAddr::from(TEST_CREATOR),
+            key: TEST_CREATOR_KEY.to_string(),
+        }).unwrap();
+        let value: ClaimableResponse = from_binary(&res).unwrap();
+        assert_eq!(value, ClaimableResponse { claimable: Uint128::zero(), claimed: Uint128::zero() });
+
+        let res = query(&deps, QueryMsg::RewardTokens {}).unwrap();
+        let value: RewardTokensResponse = from_binary(&res).unwrap();
+        assert_eq!(value.tokens, vec![
+            RewardToken {
+                contract: mock_token_contract(),
+                totals: TokenTotals { total_cred: Uint128(100), policy_totals: balanced_total(100), ..TokenTotals::default() },
+                caps: Caps::default(),
+            },
+            RewardToken {
+                contract: stable,
+                totals: TokenTotals { total_cred: Uint128(30), policy_totals: balanced_total(30), ..TokenTotals::default() },
+                caps: Caps::default(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn status_stops_handlers() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+
+        let grant = HandleMsg::GrantRole { role: Role::Pauser, address: HumanAddr::from("pauser") };
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), grant).unwrap();
+
+        let set_status = |status: ContractStatus| HandleMsg::SetStatus { status };
+        match handle(&mut deps, mock_env("someone", &[]), set_status(ContractStatus::StopAll)) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let allocate = HandleMsg::Allocate {
+            cred_id: "cred1".to_string(),
+            allocation_id: "alloc1".to_string(),
+            amount: Uint128(100),
+            policy_type: PolicyType::Balanced,
+            token: None,
+        };
+        let register = HandleMsg::RegisterUser {
+            cred_id: "cred1".to_string(),
+            scrt_address: HumanAddr::from("secret007"),
+            alias: None,
+        };
+        let assert_disabled = |res: StdResult<HandleResponse>| match res {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "This action is disabled by the contract status"),
+            res => panic!("unexpected result: {:?}", res),
+        };
 
-        let owner_raw = deps
-            .api
-            .canonical_address(&HumanAddr::from(TEST_CREATOR))
-            .unwrap();
+        let _res = handle(&mut deps, mock_env("pauser", &[]), set_status(ContractStatus::StopAllocations)).unwrap();
+        assert_disabled(handle(&mut deps, mock_env(TEST_CREATOR, &[]), allocate.clone()));
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), register.clone()).unwrap();
 
-        let _res = handle(&mut deps, env.clone(), msg).expect("contract successfully allocates cred");
+        let _res = handle(&mut deps, mock_env("pauser", &[]), set_status(ContractStatus::StopRegistrations)).unwrap();
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), allocate.clone()).unwrap();
+        assert_disabled(handle(&mut deps, mock_env(TEST_CREATOR, &[]), register));
+        let update = HandleMsg::UpdateUserAddress { cred_id: "cred1".to_string(), scrt_address: HumanAddr::from("secret008") };
+        assert_disabled(handle(&mut deps, mock_env(TEST_CREATOR, &[]), update));
+        assert_disabled(handle(&mut deps, mock_env(TEST_CREATOR, &[]), HandleMsg::DeregisterUser { cred_id: "cred1".to_string() }));
 
-        let token_contract = ContractInfo {
-            code_hash: TOKEN_HASH.to_string(),
-            address: HumanAddr(TOKEN_HASH.to_string()),
+        let _res = handle(&mut deps, mock_env("pauser", &[]), set_status(ContractStatus::StopAll)).unwrap();
+        assert_disabled(handle(&mut deps, mock_env("secret007", &[]), HandleMsg::Claim { amount: None, token: None, cred_id: None }));
+
+        let res = query(&deps, QueryMsg::ContractStatus {}).unwrap();
+        let value: ContractStatusResponse = from_binary(&res).unwrap();
+        assert_eq!(value.status, ContractStatus::StopAll);
+
+        // but the owner can still revoke keys and roles
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), HandleMsg::SetOracleKey { pubkey: None }).unwrap();
+        let revoke = HandleMsg::RevokeRole { role: Role::Pauser, address: HumanAddr::from("pauser") };
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), revoke).unwrap();
+
+        let res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), set_status(ContractStatus::Normal)).unwrap();
+        assert_eq!(res.log[2], log("status", "normal"));
+    }
+
+    #[test]
+    fn caps_limit_allocations() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+
+        let caps = Caps {
+            max_per_allocation: Some(Uint128(250)),
+            max_per_user: Some(Uint128(100)),
+            max_per_window: Some(Uint128(250)),
+            window_seconds: 3600,
         };
-        assert_config_state(&mut deps, State{
-            total_cred: Uint128::from(100u128),
-            total_users: 1,
-            token_contract,
-            owner: owner_raw,
-        });
+        match handle(&mut deps, mock_env("someone", &[]), HandleMsg::SetCaps { caps: caps.clone(), token: None }) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), HandleMsg::SetCaps { caps, token: None }).unwrap();
 
-        assert_cred_balance(&mut deps, UserCred {
-            cred_id: "cred1".to_string(),
-            scrt_address: Default::default(),
-            total_allocated: Uint128::from(100u128),
-            allocations: vec![],
-            alias: None
+        let allocate_in = |deps: &mut Extern<MockStorage, MockApi, MockQuerier>, time: u64, cred_id: &str, allocation_id: &str, amount: u128, token: Option<HumanAddr>| {
+            let mut env = mock_env(TEST_CREATOR, &[]);
+            env.block.time = time;
+            handle(deps, env, HandleMsg::Allocate {
+                cred_id: cred_id.to_string(),
+                allocation_id: allocation_id.to_string(),
+                amount: Uint128(amount),
+                policy_type: PolicyType::Balanced,
+                token,
+            })
+        };
+        let allocate_at = |deps: &mut Extern<MockStorage, MockApi, MockQuerier>, time: u64, cred_id: &str, allocation_id: &str, amount: u128| {
+            allocate_in(deps, time, cred_id, allocation_id, amount, None)
+        };
+        let assert_cap = |res: StdResult<HandleResponse>, expected: &str| match res {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, expected),
+            res => panic!("unexpected result: {:?}", res),
+        };
+
+        assert_cap(allocate_at(&mut deps, 1000, "cred1", "alloc1", 101), "Cap exceeded: at most 100 per user per allocation");
+        let _res = allocate_at(&mut deps, 1000, "cred1", "alloc1", 100).unwrap();
+        let _res = allocate_at(&mut deps, 1000, "cred2", "alloc1", 100).unwrap();
+        assert_cap(allocate_at(&mut deps, 1000, "cred3", "alloc1", 51), "Cap exceeded: at most 250 per allocation");
+
+        let headroom = |deps: &Extern<MockStorage, MockApi, MockQuerier>, allocation_id: &str, cred_id: Option<&str>, time: u64, token: Option<HumanAddr>| {
+            let res = query(deps, QueryMsg::CapHeadroom {
+                allocation_id: allocation_id.to_string(),
+                cred_id: cred_id.map(|cred_id| cred_id.to_string()),
+                time,
+                token,
+            }).unwrap();
+            from_binary::<CapHeadroomResponse>(&res).unwrap()
+        };
+        assert_eq!(headroom(&deps, "alloc1", Some("cred1"), 1000, None), CapHeadroomResponse {
+            per_allocation: Some(Uint128(50)),
+            per_user: Some(Uint128::zero()),
+            window: Some(Uint128(50)),
         });
+        assert_eq!(headroom(&deps, "alloc1", Some("cred3"), 1000, None).per_user, Some(Uint128(100)));
+        assert_eq!(headroom(&deps, "alloc1", None, 1000, None).per_user, Some(Uint128(100)));
 
-        assert_cred_allocated(&mut deps, "cred1".to_string(), "allocation 1".to_string(), true);
-    }
+        assert_cap(allocate_at(&mut deps, 2000, "cred1", "alloc2", 100), "Cap exceeded: at most 250 per window");
+        let _res = allocate_at(&mut deps, 2000, "cred1", "alloc2", 50).unwrap();
+
+        // the first allocations leave the window an hour later
+        let _res = allocate_at(&mut deps, 4600, "cred2", "alloc2", 100).unwrap();
+        assert_eq!(headroom(&deps, "alloc2", None, 4600, None).window, Some(Uint128(100)));
+
+        // allocations in another reward token count against that token's caps only
+        let stable = ContractInfo { code_hash: "stablehash".to_string(), address: HumanAddr::from("stable") };
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), HandleMsg::AddRewardToken { token: stable }).unwrap();
+        let stable = Some(HumanAddr::from("stable"));
+        let _res = allocate_in(&mut deps, 4600, "cred3", "alloc1", 1000, stable.clone()).unwrap();
 
+        let caps = Caps { max_per_allocation: Some(Uint128(1500)), ..Caps::default() };
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), HandleMsg::SetCaps { caps, token: stable.clone() }).unwrap();
+        assert_cap(allocate_in(&mut deps, 4600, "cred4", "alloc1", 501, stable.clone()), "Cap exceeded: at most 1500 per allocation");
+        let _res = allocate_in(&mut deps, 4600, "cred4", "alloc1", 500, stable.clone()).unwrap();
+
+        let value = headroom(&deps, "alloc1", Some("cred4"), 4600, stable);
+        assert_eq!(value, CapHeadroomResponse { per_allocation: Some(Uint128::zero()), per_user: None, window: None });
+
+        // a window too long to add to a block time never ends, so every entry still counts
+        let caps = Caps { max_per_window: Some(Uint128(1000)), window_seconds: u64::MAX, ..Caps::default() };
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), HandleMsg::SetCaps { caps, token: None }).unwrap();
+        let _res = allocate_at(&mut deps, 5000, "cred3", "alloc3", 100).unwrap();
+        assert_eq!(headroom(&deps, "alloc3", None, 5000, None).window, Some(Uint128(750)));
+    }
 
     #[test]
-    fn allocate_cred_twice_works() {
+    fn distributions_limit_allocations() {
         let mut deps = mock_dependencies(20, &[]);
         mock_init(&mut deps);
-        let env = mock_env(TEST_CREATOR, &coins(29416857982094508000u128, "foocoin"));
 
-        let cred_id = "cred1".to_string();
-        let msg = HandleMsg::RegisterUser {
-            cred_id,
-            scrt_address: HumanAddr("secret007".to_string()),
-            alias: None,
+        let create = HandleMsg::CreateDistribution {
+            id: "dist1".to_string(),
+            policy: PolicyType::Balanced,
+            budget: Uint128(150),
+            timestamp: 1000,
+            token: None,
         };
+        match handle(&mut deps, mock_env("someone", &[]), create.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), create.clone()).unwrap();
+        match handle(&mut deps, mock_env(TEST_CREATOR, &[]), create) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Distribution already exists"),
+            res => panic!("unexpected result: {:?}", res),
+        }
 
-        let _res = handle(&mut deps, env.clone(), msg).expect("contract successfully registers cred");
-        assert_registered(&mut deps, "cred1", true);
+        let allocate = |deps: &mut Extern<MockStorage, MockApi, MockQuerier>, cred_id: &str, amount: u128, policy_type: PolicyType| {
+            handle(deps, mock_env(TEST_CREATOR, &[]), HandleMsg::Allocate {
+                cred_id: cred_id.to_string(),
+                allocation_id: "dist1".to_string(),
+                amount: Uint128(amount),
+                policy_type,
+                token: None,
+            })
+        };
+        let assert_err = |res: StdResult<HandleResponse>, expected: &str| match res {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, expected),
+            res => panic!("unexpected result: {:?}", res),
+        };
 
-        let cred_id = "cred1".to_string();
-        let msg = HandleMsg::Allocate {
-            allocation_id: "allocation 1".to_string(),
+        assert_err(allocate(&mut deps, "cred1", 100, PolicyType::Immediate), "Policy does not match distribution");
+
+        // the budget is in the default token, other reward tokens don't count towards it
+        let stable = ContractInfo { code_hash: "stablehash".to_string(), address: HumanAddr::from("stable") };
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), HandleMsg::AddRewardToken { token: stable }).unwrap();
+        assert_err(handle(&mut deps, mock_env(TEST_CREATOR, &[]), HandleMsg::Allocate {
+            cred_id: "cred1".to_string(),
+            allocation_id: "dist1".to_string(),
+            amount: Uint128(100),
             policy_type: PolicyType::Balanced,
-            cred_id: cred_id.clone(),
-            amount: Uint128::from(14708428991047254000u128)
-        };
+            token: Some(HumanAddr::from("stable")),
+        }), "Token does not match distribution");
+        let _res = allocate(&mut deps, "cred1", 100, PolicyType::Balanced).unwrap();
+        assert_err(allocate(&mut deps, "cred2", 60, PolicyType::Balanced), "Distribution budget exceeded: 50 remaining");
+        let _res = allocate(&mut deps, "cred2", 50, PolicyType::Balanced).unwrap();
 
-        let owner_raw = deps
-            .api
-            .canonical_address(&HumanAddr::from(TEST_CREATOR))
-            .unwrap();
+        let res = query(&deps, QueryMsg::Distribution { id: "dist1".to_string() }).unwrap();
+        let value: DistributionResponse = from_binary(&res).unwrap();
+        assert_eq!(value.distribution.allocated, Uint128(150));
+        assert_eq!(value.distribution.recipients, 2);
+        assert!(!value.distribution.finalized);
+        assert_eq!(value.remaining, Uint128::zero());
 
-        let _res = handle(&mut deps, env.clone(), msg).expect("contract successfully allocates cred");
+        let finalize = HandleMsg::FinalizeDistribution { id: "dist1".to_string() };
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), finalize).unwrap();
+        assert_err(allocate(&mut deps, "cred3", 0, PolicyType::Balanced), "Distribution is finalized");
 
-        let token_contract = ContractInfo {
-            code_hash: TOKEN_HASH.to_string(),
-            address: HumanAddr(TOKEN_HASH.to_string()),
+        let res = query(&deps, QueryMsg::Distribution { id: "dist1".to_string() }).unwrap();
+        let value: DistributionResponse = from_binary(&res).unwrap();
+        assert!(value.distribution.finalized);
+    }
+
+    #[test]
+    fn balanced_allocations_vest() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        mock_register(&mut deps, "cred1", "secret007");
+
+        let terms = VestingTerms { cliff: 100, duration: 1000 };
+        let set_vesting = HandleMsg::SetBalancedVesting { terms: Some(terms) };
+        match handle(&mut deps, mock_env("someone", &[]), set_vesting.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), set_vesting).unwrap();
+
+        let allocate_at = |deps: &mut Extern<MockStorage, MockApi, MockQuerier>, allocation_id: &str, policy_type: PolicyType| {
+            let mut env = mock_env(TEST_CREATOR, &[]);
+            env.block.time = 1000;
+            handle(deps, env, HandleMsg::Allocate {
+                cred_id: "cred1".to_string(),
+                allocation_id: allocation_id.to_string(),
+                amount: Uint128(1000),
+                policy_type,
+                token: None,
+            }).unwrap()
+        };
+        let claim_at = |deps: &mut Extern<MockStorage, MockApi, MockQuerier>, time: u64| {
+            let mut env = mock_env("secret007", &[]);
+            env.block.time = time;
+            handle(deps, env, HandleMsg::Claim { amount: None, token: None, cred_id: None })
         };
-        assert_config_state(&mut deps, State{
-            total_cred: Uint128::from(14708428991047254000u128),
-            total_users: 1,
-            token_contract,
-            owner: owner_raw.clone(),
-        });
 
-        assert_cred_balance(&mut deps, UserCred {
+        // immediate allocations are still paid right away
+        let res = allocate_at(&mut deps, "alloc1", PolicyType::Immediate);
+        assert_eq!(res.messages.len(), 1);
+        let res = allocate_at(&mut deps, "alloc2", PolicyType::Balanced);
+        assert!(res.messages.is_empty());
+        let res = allocate_at(&mut deps, "alloc3", PolicyType::Vesting(VestingTerms { cliff: 0, duration: 0 }));
+        assert!(res.messages.is_empty());
+
+        let state = config_read(&deps.storage).load().unwrap();
+        assert_eq!(state.total_vesting, Uint128(2000));
+
+        // alloc3 vests at once, alloc2 not before its cliff
+        let _res = claim_at(&mut deps, 1050).unwrap();
+        assert_claimable(&deps, "cred1", 0, 1000);
+        match claim_at(&mut deps, 1050) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Nothing to claim"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let res = claim_at(&mut deps, 1500).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_claimable(&deps, "cred1", 0, 1500);
+
+        let res = query(&deps, QueryMsg::GetVesting {
             cred_id: "cred1".to_string(),
-            scrt_address: Default::default(),
-            total_allocated: Uint128::from(14708428991047254000u128),
-            allocations: vec![],
-            alias: None
-        });
+            time: 1600,
+            address: HumanAddr::from(TEST_CREATOR),
+            key: TEST_CREATOR_KEY.to_string(),
+        }).unwrap();
+        let value: VestingResponse = from_binary(&res).unwrap();
+        // alloc3 was released in full and dropped
+        assert_eq!(value.schedules.len(), 1);
+        assert_eq!(value.schedules[0].allocation_id, "alloc2");
+        assert_eq!(value.schedules[0].vested, Uint128(600));
+        assert_eq!(value.schedules[0].unvested, Uint128(400));
+        assert_eq!(value.schedules[0].claimed, Uint128(500));
 
+        let state = config_read(&deps.storage).load().unwrap();
+        assert_eq!(state.total_vesting, Uint128(500));
+        assert_eq!(state.total_unclaimed, Uint128::zero());
+    }
 
-        let msg = HandleMsg::Allocate {
-            allocation_id: "allocation 2".to_string(),
-            policy_type: PolicyType::Balanced,
-            cred_id: cred_id.clone(),
-            amount: Uint128::from(29416857982094508000u128)
+    #[test]
+    fn vesting_large_amounts_doesnt_overflow() {
+        let schedule = VestingSchedule {
+            allocation_id: "alloc1".to_string(),
+            token: None,
+            amount: Uint128(3 * 10u128.pow(38)),
+            start: 1000,
+            terms: VestingTerms { cliff: 0, duration: 3 },
+            released: Uint128::zero(),
         };
+        assert_eq!(schedule.vested(1001), Uint128(10u128.pow(38)));
+        assert_eq!(schedule.vested(1002), Uint128(2 * 10u128.pow(38)));
+        assert_eq!(schedule.vested(1003), Uint128(3 * 10u128.pow(38)));
+    }
 
-        let handle_res = handle(&mut deps, env.clone(), msg).expect("contract successfully allocates more cred");
+    #[test]
+    fn recent_and_special_policies_are_recorded() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        mock_register(&mut deps, "cred1", "secret007");
 
-        let _msg = handle_res.messages.get(0).expect("no message");
+        // the JSON the client sends for SourceCred's RECENT and SPECIAL policies
+        let recent: PolicyType = from_binary(&Binary(br#"{"Recent":{"discount":"0.5"}}"#.to_vec())).unwrap();
+        assert_eq!(recent, PolicyType::Recent { discount: Decimal::percent(50) });
+        let special: PolicyType = from_binary(&Binary(
+            br#"{"Special":{"memo":"thanks for the docs","recipient":"cred1"}}"#.to_vec()
+        )).unwrap();
 
-        let token_contract = ContractInfo {
-            code_hash: TOKEN_HASH.to_string(),
-            address: HumanAddr(TOKEN_HASH.to_string()),
+        for (allocation_id, policy_type) in &[("alloc1", &recent), ("alloc2", &special), ("alloc3", &recent)] {
+            let res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), HandleMsg::Allocate {
+                cred_id: "cred1".to_string(),
+                allocation_id: allocation_id.to_string(),
+                amount: Uint128(100),
+                policy_type: (*policy_type).clone(),
+                token: None,
+            }).unwrap();
+            assert_eq!(res.messages.len(), 1);
+        }
+
+        let allocation = allocations_read(&deps.storage, "cred1").load(b"alloc2").unwrap();
+        assert_eq!(allocation.policy, special);
+
+        let state = config_read(&deps.storage).load().unwrap();
+        assert_eq!(state.policy_totals, vec![
+            PolicyTotal { policy: "recent".to_string(), total: Uint128(200) },
+            PolicyTotal { policy: "special".to_string(), total: Uint128(100) },
+        ]);
+    }
+
+    #[test]
+    fn revoke_allocation_reverses_accounting() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        let env = mock_env(TEST_CREATOR, &[]);
+        let _res = handle(&mut deps, env.clone(), HandleMsg::SetClaimMode { claim_mode: ClaimMode::Claim }).unwrap();
+        mock_register(&mut deps, "cred1", "secret007");
+
+        let allocate = |deps: &mut Extern<MockStorage, MockApi, MockQuerier>, cred_id: &str, allocation_id: &str, amount: u128| {
+            handle(deps, mock_env(TEST_CREATOR, &[]), HandleMsg::Allocate {
+                cred_id: cred_id.to_string(),
+                allocation_id: allocation_id.to_string(),
+                amount: Uint128(amount),
+                policy_type: PolicyType::Balanced,
+                token: None,
+            }).unwrap()
         };
-        assert_config_state(&mut deps, State{
-            total_cred: Uint128::from(44125286973141762000u128),
-            total_users: 1,
-            token_contract,
-            owner: owner_raw,
-        });
+        let revoke = |cred_id: &str, allocation_id: &str| HandleMsg::RevokeAllocation {
+            cred_id: cred_id.to_string(),
+            allocation_id: allocation_id.to_string(),
+            reason: "wrong cred".to_string(),
+        };
+
+        allocate(&mut deps, "cred1", "alloc1", 100);
+        allocate(&mut deps, "cred1", "alloc2", 50);
+        let _res = handle(&mut deps, mock_env("secret007", &[]), HandleMsg::Claim {
+            amount: Some(Uint128(120)),
+            token: None,
+            cred_id: None,
+        }).unwrap();
+
+        match handle(&mut deps, mock_env("someone", &[]), revoke("cred1", "alloc1")) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // the unclaimed 30 is taken back, the 70 already paid out is owed
+        let _res = handle(&mut deps, env.clone(), revoke("cred1", "alloc1")).unwrap();
+        assert_claimable(&deps, "cred1", 0, 120);
+        assert_cred_balance(&deps, "cred1", Uint128(50));
+        assert_cred_allocated(&mut deps, "cred1".to_string(), "alloc1".to_string(), false);
+        let state = config_read(&deps.storage).load().unwrap();
+        assert_eq!(state.total_cred, Uint128(50));
+        assert_eq!(state.total_unclaimed, Uint128::zero());
+
+        match handle(&mut deps, env.clone(), revoke("cred1", "alloc1")) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Allocation does not exist"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // the debt is paid off by the next allocation first
+        allocate(&mut deps, "cred1", "alloc3", 100);
+        assert_claimable(&deps, "cred1", 30, 120);
+        assert_cred_balance(&deps, "cred1", Uint128(150));
+
+        allocate(&mut deps, "cred2", "alloc1", 40);
+        let _res = handle(&mut deps, env.clone(), revoke("cred2", "alloc1")).unwrap();
+        assert_escrowed(&deps, "cred2", 0);
+
+        // an unreleased allocation in a token nothing is escrowed in
+        let stable = ContractInfo { code_hash: "stablehash".to_string(), address: HumanAddr::from("stable") };
+        let _res = handle(&mut deps, env.clone(), HandleMsg::AddRewardToken { token: stable }).unwrap();
+        allocate(&mut deps, "cred2", "alloc2", 40);
+        let _res = handle(&mut deps, env.clone(), HandleMsg::Allocate {
+            cred_id: "cred2".to_string(),
+            allocation_id: "alloc3".to_string(),
+            amount: Uint128(20),
+            policy_type: PolicyType::Vesting(VestingTerms { cliff: 0, duration: 1000 }),
+            token: Some(HumanAddr::from("stable")),
+        }).unwrap();
+        let res = handle(&mut deps, env, revoke("cred2", "alloc3")).unwrap();
+        assert!(res.log.contains(&log("debt", "0")));
+        assert_escrowed(&deps, "cred2", 40);
 
-        assert_cred_balance(&mut deps, UserCred {
+        let res = query(&deps, QueryMsg::GetRevocations {
             cred_id: "cred1".to_string(),
-            scrt_address: Default::default(),
-            total_allocated: Uint128::from(44125286973141762000u128),
-            allocations: vec![],
-            alias: None
-        })
+            start_after: None,
+            limit: None,
+            address: HumanAddr::from(TEST_CREATOR),
+            key: TEST_CREATOR_KEY.to_string(),
+        }).unwrap();
+        let value: RevocationsResponse = from_binary(&res).unwrap();
+        assert_eq!(value.revocations, vec![RevocationRecord {
+            position: 0,
+            revocation: Revocation {
+                allocation_id: "alloc1".to_string(),
+                token: None,
+                amount: Uint128(100),
+                debt: Uint128(70),
+                reason: "wrong cred".to_string(),
+                revoked_by: HumanAddr::from(TEST_CREATOR),
+                time: mock_env(TEST_CREATOR, &[]).block.time,
+            },
+        }]);
+
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), revoke("cred2", "alloc2")).unwrap();
+        let res = query(&deps, QueryMsg::GetRevocations {
+            cred_id: "cred2".to_string(),
+            start_after: Some(1),
+            limit: Some(1),
+            address: HumanAddr::from(TEST_CREATOR),
+            key: TEST_CREATOR_KEY.to_string(),
+        }).unwrap();
+        let value: RevocationsResponse = from_binary(&res).unwrap();
+        assert_eq!(value.revocations.len(), 1);
+        assert_eq!(value.revocations[0].position, 2);
+        assert_eq!(value.revocations[0].revocation.allocation_id, "alloc2");
     }
 
     #[test]
-    fn allocate_cred_duplicate_fails() {
+    fn revoked_debt_survives_deregistration() {
         let mut deps = mock_dependencies(20, &[]);
         mock_init(&mut deps);
-        let env = mock_env(TEST_CREATOR, &coins(100u128, "foocoin"));
+        let env = mock_env(TEST_CREATOR, &[]);
+        let user_env = mock_env("secret007", &[]);
+        let _res = handle(&mut deps, env.clone(), HandleMsg::SetClaimMode { claim_mode: ClaimMode::Claim }).unwrap();
 
-        let cred_id = "cred1".to_string();
-        let msg = HandleMsg::RegisterUser {
-            cred_id,
-            scrt_address: HumanAddr("secret007".to_string()),
+        let registrar_key = SigningKey::from_bytes(&[7u8; 32]).unwrap();
+        let pubkey = Binary(registrar_key.verify_key().to_bytes().to_vec());
+        let _res = handle(&mut deps, env.clone(), HandleMsg::SetRegistrarKey { pubkey: Some(pubkey) }).unwrap();
+        let expiry = user_env.block.time + 3600;
+        let claim_identity = |registrations: u64| HandleMsg::ClaimIdentity {
+            cred_id: "cred1".to_string(),
             alias: None,
+            expiry,
+            signature: sign_attestation(&registrar_key, "cred1", "secret007", registrations, expiry),
         };
+        let deregister = HandleMsg::DeregisterUser { cred_id: "cred1".to_string() };
 
-        let _res = handle(&mut deps, env.clone(), msg).expect("contract successfully registers cred");
-        assert_registered(&mut deps, "cred1", true);
+        let allocate = |deps: &mut Extern<MockStorage, MockApi, MockQuerier>, allocation_id: &str, amount: u128| {
+            handle(deps, mock_env(TEST_CREATOR, &[]), HandleMsg::Allocate {
+                cred_id: "cred1".to_string(),
+                allocation_id: allocation_id.to_string(),
+                amount: Uint128(amount),
+                policy_type: PolicyType::Balanced,
+                token: None,
+            }).unwrap()
+        };
+        let revoke = |allocation_id: &str| HandleMsg::RevokeAllocation {
+            cred_id: "cred1".to_string(),
+            allocation_id: allocation_id.to_string(),
+            reason: "wrong cred".to_string(),
+        };
 
-        let cred_id = "cred1".to_string();
-        let msg = HandleMsg::Allocate {
-            allocation_id: "allocation 1".to_string(),
-            policy_type: PolicyType::Balanced,
-            cred_id,
-            amount: Uint128::from(100u128)
+        let _res = handle(&mut deps, user_env.clone(), claim_identity(0)).unwrap();
+        allocate(&mut deps, "alloc1", 100);
+        let _res = handle(&mut deps, user_env.clone(), HandleMsg::Claim { amount: None, token: None, cred_id: None }).unwrap();
+        let _res = handle(&mut deps, env.clone(), revoke("alloc1")).unwrap();
+
+        // registering again doesn't clear the debt
+        let _res = handle(&mut deps, user_env.clone(), deregister.clone()).expect("user deregisters themselves");
+        let _res = handle(&mut deps, user_env.clone(), claim_identity(1)).unwrap();
+        allocate(&mut deps, "alloc2", 60);
+        assert_claimable(&deps, "cred1", 0, 100);
+
+        // what is revoked while deregistered is owed too
+        let _res = handle(&mut deps, user_env.clone(), deregister).unwrap();
+        let res = handle(&mut deps, env.clone(), revoke("alloc2")).unwrap();
+        assert!(res.log.contains(&log("debt", "60")));
+        let _res = handle(&mut deps, user_env, claim_identity(2)).unwrap();
+        allocate(&mut deps, "alloc3", 150);
+        assert_claimable(&deps, "cred1", 50, 100);
+        assert_cred_balance(&deps, "cred1", Uint128(150));
+    }
+
+    #[test]
+    fn claim_from_root_works() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+
+        let leaf = |cred_id: &str, amount: u128| {
+            sha_256(&to_vec(&Receipt { cred_id: cred_id.to_string(), amount: Uint128(amount) }).unwrap())
         };
+        let hash_pair = |a: [u8; 32], b: [u8; 32]| {
+            let (first, second) = if a <= b { (a, b) } else { (b, a) };
+            sha_256(&[first.as_ref(), second.as_ref()].concat())
+        };
+        let leaves = [leaf("cred1", 100), leaf("cred2", 200), leaf("cred3", 50)];
+        let left = hash_pair(leaves[0], leaves[1]);
+        let root = hash_pair(left, leaves[2]);
 
-        let owner_raw = deps
-            .api
-            .canonical_address(&HumanAddr::from(TEST_CREATOR))
-            .unwrap();
+        // the receipts add up to more than the declared total
+        let publish = HandleMsg::PublishDistributionRoot {
+            distribution_id: "dist1".to_string(),
+            merkle_root: Binary(root.to_vec()),
+            total: Uint128(300),
+            token: None,
+        };
+        match handle(&mut deps, mock_env("someone", &[]), publish.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), publish.clone()).unwrap();
+        match handle(&mut deps, mock_env(TEST_CREATOR, &[]), publish) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Distribution root already published"),
+            res => panic!("unexpected result: {:?}", res),
+        }
 
-        let _res = handle(&mut deps, env.clone(), msg.clone()).expect("contract successfully allocates cred");
+        mock_register(&mut deps, "cred1", "secret007");
+        mock_register(&mut deps, "cred2", "secret008");
+        mock_register(&mut deps, "cred3", "secret009");
 
-        let token_contract = ContractInfo {
-            code_hash: TOKEN_HASH.to_string(),
-            address: HumanAddr(TOKEN_HASH.to_string()),
+        let claim = |deps: &mut Extern<MockStorage, MockApi, MockQuerier>, sender: &str, amount: u128, proof: &[[u8; 32]]| {
+            handle(deps, mock_env(sender, &[]), HandleMsg::ClaimFromRoot {
+                distribution_id: "dist1".to_string(),
+                amount: Uint128(amount),
+                proof: proof.iter().map(|hash| Binary(hash.to_vec())).collect(),
+                cred_id: None,
+            })
         };
-        assert_config_state(&mut deps, State{
-            total_cred: Uint128::from(100u128),
-            total_users: 1,
-            token_contract: token_contract.clone(),
-            owner: owner_raw.clone(),
-        });
+        let assert_err = |res: StdResult<HandleResponse>, expected: &str| match res {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, expected),
+            res => panic!("unexpected result: {:?}", res),
+        };
+
+        assert_err(claim(&mut deps, "secret007", 150, &[leaves[1], leaves[2]]), "Invalid proof");
+        let res = claim(&mut deps, "secret007", 100, &[leaves[1], leaves[2]]).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_cred_balance(&deps, "cred1", Uint128(100));
+        assert_cred_allocated(&mut deps, "cred1".to_string(), "dist1".to_string(), true);
+        assert_err(claim(&mut deps, "secret007", 100, &[leaves[1], leaves[2]]), "Already claimed");
 
-        assert_cred_balance(&mut deps, UserCred {
+        // a revoked claim stays claimed
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), HandleMsg::RevokeAllocation {
             cred_id: "cred1".to_string(),
-            scrt_address: Default::default(),
-            total_allocated: Uint128::from(100u128),
-            allocations: vec![],
-            alias: None
-        });
+            allocation_id: "dist1".to_string(),
+            reason: "sybil".to_string(),
+        }).unwrap();
+        assert_err(claim(&mut deps, "secret007", 100, &[leaves[1], leaves[2]]), "Already claimed");
 
-        let _res = handle(&mut deps, env.clone(), msg);
+        let _res = claim(&mut deps, "secret008", 200, &[leaves[0], leaves[2]]).unwrap();
+        assert_err(claim(&mut deps, "secret009", 50, &[left]), "Claim exceeds distribution total");
 
-        match _res {
-            Ok(_) => panic!("expected error"),
-            Err(StdError::GenericErr { msg, .. }) => {
-                assert_eq!(msg, "Already allocated")
-            }
-            Err(e) => panic!("unexpected error: {:?}", e),
+        let res = query(&deps, QueryMsg::DistributionRoot { distribution_id: "dist1".to_string() }).unwrap();
+        let value: DistributionRootResponse = from_binary(&res).unwrap();
+        assert_eq!(value.root.claimed, Uint128(300));
+        assert_eq!(value.remaining, Uint128::zero());
+    }
+
+    #[test]
+    fn claim_from_root_counts_towards_distribution() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        let env = mock_env(TEST_CREATOR, &[]);
+        mock_register(&mut deps, "cred1", "secret007");
+        mock_register(&mut deps, "cred2", "secret008");
+
+        let leaf = |cred_id: &str, amount: u128| {
+            sha_256(&to_vec(&Receipt { cred_id: cred_id.to_string(), amount: Uint128(amount) }).unwrap())
+        };
+        let leaves = [leaf("cred1", 100), leaf("cred2", 200)];
+        let (first, second) = if leaves[0] <= leaves[1] { (leaves[0], leaves[1]) } else { (leaves[1], leaves[0]) };
+        let root = sha_256(&[first.as_ref(), second.as_ref()].concat());
+
+        let _res = handle(&mut deps, env.clone(), HandleMsg::CreateDistribution {
+            id: "dist1".to_string(),
+            policy: PolicyType::Immediate,
+            budget: Uint128(250),
+            timestamp: 1_600_000_000,
+            token: None,
+        }).unwrap();
+        let _res = handle(&mut deps, env.clone(), HandleMsg::PublishDistributionRoot {
+            distribution_id: "dist1".to_string(),
+            merkle_root: Binary(root.to_vec()),
+            total: Uint128(300),
+            token: None,
+        }).unwrap();
+
+        let claim = |deps: &mut Extern<MockStorage, MockApi, MockQuerier>, sender: &str, amount: u128, sibling: [u8; 32]| {
+            handle(deps, mock_env(sender, &[]), HandleMsg::ClaimFromRoot {
+                distribution_id: "dist1".to_string(),
+                amount: Uint128(amount),
+                proof: vec![Binary(sibling.to_vec())],
+                cred_id: None,
+            })
+        };
+        let assert_err = |res: StdResult<HandleResponse>, expected: &str| match res {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, expected),
+            res => panic!("unexpected result: {:?}", res),
+        };
+
+        let _res = claim(&mut deps, "secret007", 100, leaves[1]).unwrap();
+        assert_err(claim(&mut deps, "secret008", 200, leaves[0]), "Distribution budget exceeded: 150 remaining");
+
+        let res = query(&deps, QueryMsg::Distribution { id: "dist1".to_string() }).unwrap();
+        let value: DistributionResponse = from_binary(&res).unwrap();
+        assert_eq!(value.distribution.allocated, Uint128(100));
+        assert_eq!(value.distribution.recipients, 1);
+
+        let _res = handle(&mut deps, env, HandleMsg::FinalizeDistribution { id: "dist1".to_string() }).unwrap();
+        assert_err(claim(&mut deps, "secret008", 200, leaves[0]), "Distribution is finalized");
+    }
+
+    #[test]
+    fn signed_allocations_can_be_relayed() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        mock_register(&mut deps, "cred1", "secret007");
+
+        let oracle_key = SigningKey::from_bytes(&[9u8; 32]).unwrap();
+        let payload = |nonce: u64, allocation_id: &str| SignedAllocations {
+            contract_address: HumanAddr::from(MOCK_CONTRACT_ADDR),
+            nonce,
+            allocation_id: allocation_id.to_string(),
+            policy_type: PolicyType::Immediate,
+            receipts: vec![
+                Receipt { cred_id: "cred1".to_string(), amount: Uint128(100) },
+                Receipt { cred_id: "cred2".to_string(), amount: Uint128(50) },
+            ],
+            mode: BatchMode::Reject,
+            token: None,
+        };
+        let submit = |payload: SignedAllocations| {
+            let signature: Signature = oracle_key.sign(&to_vec(&payload).unwrap());
+            HandleMsg::SubmitSignedAllocations { payload, signature: Binary(signature.as_ref().to_vec()) }
+        };
+        let assert_err = |res: StdResult<HandleResponse>, expected: &str| match res {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, expected),
+            res => panic!("unexpected result: {:?}", res),
+        };
+
+        // the recipient relays it, without any role
+        let relayer = mock_env("secret007", &[]);
+        assert_err(handle(&mut deps, relayer.clone(), submit(payload(1, "alloc1"))), "Signed allocations are not enabled");
+
+        let pubkey = Binary(oracle_key.verify_key().to_bytes().to_vec());
+        match handle(&mut deps, relayer.clone(), HandleMsg::SetOracleKey { pubkey: Some(pubkey.clone()) }) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
         }
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), HandleMsg::SetOracleKey { pubkey: Some(pubkey) }).unwrap();
 
-        assert_config_state(&mut deps, State{
-            total_cred: Uint128::from(100u128),
-            total_users: 1,
-            token_contract,
-            owner: owner_raw,
-        });
+        let mut tampered = submit(payload(1, "alloc1"));
+        if let HandleMsg::SubmitSignedAllocations { payload, .. } = &mut tampered {
+            payload.receipts[0].amount = Uint128(1000);
+        }
+        assert_err(handle(&mut deps, relayer.clone(), tampered), "Invalid oracle signature");
 
-        assert_cred_balance(&mut deps, UserCred {
-            cred_id: "cred1".to_string(),
-            scrt_address: Default::default(),
-            total_allocated: Uint128::from(100u128),
-            allocations: vec![],
-            alias: None
-        })
+        let res = handle(&mut deps, relayer.clone(), submit(payload(1, "alloc1"))).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_cred_balance(&deps, "cred1", Uint128(100));
+        assert_escrowed(&deps, "cred2", 50);
+
+        assert_err(handle(&mut deps, relayer.clone(), submit(payload(1, "alloc2"))), "Nonce must be greater than 1");
+        let _res = handle(&mut deps, relayer.clone(), submit(payload(5, "alloc2"))).unwrap();
+        assert_err(handle(&mut deps, relayer.clone(), submit(payload(3, "alloc3"))), "Nonce must be greater than 5");
+
+        let mut other_contract = payload(6, "alloc3");
+        other_contract.contract_address = HumanAddr::from("secret1other");
+        assert_err(handle(&mut deps, relayer, submit(other_contract)), "Payload is for another contract");
     }
 
+    #[test]
+    fn list_users_pages_in_registration_order() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        for (cred_id, address) in &[("cred1", "secret001"), ("cred2", "secret002"), ("cred3", "secret003"), ("cred4", "secret004")] {
+            mock_register(&mut deps, cred_id, address);
+        }
+
+        // cred2 moves to the end of the list when it registers again
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), HandleMsg::DeregisterUser { cred_id: "cred2".to_string() }).unwrap();
+        mock_register(&mut deps, "cred2", "secret005");
+
+        let list = |deps: &Extern<MockStorage, MockApi, MockQuerier>, address: &str, key: &str, start_after: Option<u64>| {
+            query(deps, QueryMsg::ListUsers {
+                start_after,
+                limit: Some(2),
+                address: HumanAddr::from(address),
+                key: key.to_string(),
+            }).map(|res| from_binary::<ListUsersResponse>(&res).unwrap().users)
+        };
+
+        let page = list(&deps, TEST_CREATOR, TEST_CREATOR_KEY, None).unwrap();
+        assert_eq!(page.iter().map(|user| (user.position, user.cred_id.as_str())).collect::<Vec<_>>(), vec![(0, "cred1"), (2, "cred3")]);
+        assert_eq!(page[0].scrt_address, HumanAddr::from("secret001"));
+
+        let page = list(&deps, TEST_CREATOR, TEST_CREATOR_KEY, Some(2)).unwrap();
+        assert_eq!(page.iter().map(|user| (user.position, user.cred_id.as_str())).collect::<Vec<_>>(), vec![(3, "cred4"), (4, "cred2")]);
+        assert_eq!(page[1].scrt_address, HumanAddr::from("secret005"));
+        assert!(list(&deps, TEST_CREATOR, TEST_CREATOR_KEY, Some(4)).unwrap().is_empty());
+
+        // a registered user can't list everyone
+        let _res = handle(&mut deps, mock_env("secret001", &[]), HandleMsg::SetViewingKey { key: "user_key".to_string(), padding: None }).unwrap();
+        match list(&deps, "secret001", "user_key", None) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
 
     #[test]
-    fn register_twice_fails() {
+    fn get_allocations_pages_and_filters() {
         let mut deps = mock_dependencies(20, &[]);
-        let env = mock_env(TEST_CREATOR, &coins(1000, "hush money"));
         mock_init(&mut deps);
+        mock_register(&mut deps, "cred1", "secret007");
 
-        let cred_id = "cred1".to_string();
-        let msg = HandleMsg::RegisterUser {
-            cred_id,
-            scrt_address: HumanAddr("secret007".to_string()),
-            alias: None,
+        let policies = vec![
+            ("alloc1", PolicyType::Balanced),
+            ("alloc2", PolicyType::Immediate),
+            ("alloc3", PolicyType::Balanced),
+            ("alloc4", PolicyType::Recent { discount: Decimal::percent(10) }),
+        ];
+        for (i, (allocation_id, policy_type)) in policies.into_iter().enumerate() {
+            let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), HandleMsg::Allocate {
+                cred_id: "cred1".to_string(),
+                allocation_id: allocation_id.to_string(),
+                amount: Uint128(10 * (i as u128 + 1)),
+                policy_type,
+                token: None,
+            }).unwrap();
+        }
+        let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), HandleMsg::RevokeAllocation {
+            cred_id: "cred1".to_string(),
+            allocation_id: "alloc2".to_string(),
+            reason: "duplicate".to_string(),
+        }).unwrap();
+
+        let list = |deps: &Extern<MockStorage, MockApi, MockQuerier>, start_after: Option<u64>, policy: Option<PolicyType>| {
+            let res = query(deps, QueryMsg::GetAllocations {
+                cred_id: "cred1".to_string(),
+                start_after,
+                limit: Some(2),
+                policy,
+                address: HumanAddr::from(TEST_CREATOR),
+                key: TEST_CREATOR_KEY.to_string(),
+            }).unwrap();
+            let value: AllocationsResponse = from_binary(&res).unwrap();
+            let allocations = value.allocations
+                .into_iter()
+                .map(|record| (record.position, record.allocation.allocation_id, record.allocation.amount))
+                .collect::<Vec<_>>();
+            (allocations, value.next_start_after)
         };
 
-        let _res = handle(&mut deps, env.clone(), msg.clone()).expect("contract successfully registers cred");
-        assert_registered(&mut deps, "cred1", true);
-        let _res = handle(&mut deps, env, msg);
-        match _res {
-            Ok(_) => panic!("expected error"),
-            Err(StdError::GenericErr { msg, .. }) => {
-                assert_eq!(msg, "User already exists")
-            }
-            Err(e) => panic!("unexpected error: {:?}", e),
+        assert_eq!(list(&deps, None, None), (vec![
+            (0, "alloc1".to_string(), Uint128(10)),
+            (2, "alloc3".to_string(), Uint128(30)),
+        ], Some(2)));
+        assert_eq!(list(&deps, Some(2), None), (vec![(3, "alloc4".to_string(), Uint128(40))], None));
+
+        // filtering by kind ignores the discount
+        let recent = PolicyType::Recent { discount: Decimal::percent(50) };
+        assert_eq!(list(&deps, None, Some(recent)), (vec![(3, "alloc4".to_string(), Uint128(40))], None));
+        assert_eq!(list(&deps, None, Some(PolicyType::Immediate)), (vec![], None));
+
+        // a filter matching nothing stops after MAX_SCAN_SIZE entries
+        for i in 0..=MAX_SCAN_SIZE {
+            let _res = handle(&mut deps, mock_env(TEST_CREATOR, &[]), HandleMsg::Allocate {
+                cred_id: "cred1".to_string(),
+                allocation_id: format!("bulk{}", i),
+                amount: Uint128(1),
+                policy_type: PolicyType::Immediate,
+                token: None,
+            }).unwrap();
+        }
+        assert_eq!(list(&deps, Some(3), Some(PolicyType::Balanced)), (vec![], Some(3 + MAX_SCAN_SIZE)));
+        assert_eq!(list(&deps, Some(3 + MAX_SCAN_SIZE), Some(PolicyType::Balanced)), (vec![], None));
+    }
+
+    #[test]
+    fn reverse_lookups_respect_uniqueness() {
+        let mut deps = mock_dependencies(20, &[]);
+        mock_init(&mut deps);
+        let env = mock_env(TEST_CREATOR, &[]);
+
+        let register = |cred_id: &str, scrt_address: &str| HandleMsg::RegisterUser {
+            cred_id: cred_id.to_string(),
+            scrt_address: HumanAddr::from(scrt_address),
+            alias: Some("levackt".to_string()),
+        };
+        let assert_err = |res: StdResult<HandleResponse>, expected: &str| match res {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, expected),
+            res => panic!("unexpected result: {:?}", res),
+        };
+        let by_alias = |deps: &Extern<MockStorage, MockApi, MockQuerier>| {
+            let res = query(deps, QueryMsg::CredIdByAlias { alias: "levackt".to_string() }).unwrap();
+            from_binary::<CredIdsResponse>(&res).unwrap().cred_ids
+        };
+        let by_address = |deps: &Extern<MockStorage, MockApi, MockQuerier>| {
+            let res = query(deps, QueryMsg::CredIdByAddress {
+                address: HumanAddr::from("secret007"),
+                key: "user_key".to_string(),
+            }).unwrap();
+            from_binary::<CredIdsResponse>(&res).unwrap().cred_ids
+        };
+
+        let _res = handle(&mut deps, env.clone(), register("cred1", "secret007")).unwrap();
+        assert_err(handle(&mut deps, env.clone(), register("cred2", "secret007")), "Address already registered to another cred");
+        assert_err(handle(&mut deps, env.clone(), register("cred2", "secret008")), "Alias already registered to another cred");
+
+        let _res = handle(&mut deps, mock_env("secret007", &[]), HandleMsg::SetViewingKey { key: "user_key".to_string(), padding: None }).unwrap();
+        assert_eq!(by_alias(&deps), vec!["cred1".to_string()]);
+        assert_eq!(by_address(&deps), vec!["cred1".to_string()]);
+
+        let uniqueness = HandleMsg::SetUniqueness { uniqueness: Uniqueness { addresses: false, aliases: false } };
+        match handle(&mut deps, mock_env("secret007", &[]), uniqueness.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            res => panic!("unexpected result: {:?}", res),
         }
+        let _res = handle(&mut deps, env.clone(), uniqueness).unwrap();
+
+        // the address now resolves to every cred registered with it
+        let _res = handle(&mut deps, env.clone(), register("cred2", "secret007")).unwrap();
+        assert_eq!(by_alias(&deps), vec!["cred1".to_string(), "cred2".to_string()]);
+        assert_eq!(by_address(&deps), vec!["cred1".to_string(), "cred2".to_string()]);
+
+        // and claims must pick one of them
+        let claim = |cred_id: Option<&str>| HandleMsg::Claim { amount: None, token: None, cred_id: cred_id.map(String::from) };
+        assert_err(handle(&mut deps, mock_env("secret007", &[]), claim(None)), "Address is registered to several creds, pick one with cred_id");
+        assert_err(handle(&mut deps, mock_env("secret007", &[]), claim(Some("cred3"))), "Address is not registered to cred cred3");
+        assert_err(handle(&mut deps, mock_env("secret007", &[]), claim(Some("cred2"))), "Nothing to claim");
+        let res = query(&deps, QueryMsg::GetClaimable {
+            cred_id: "cred1".to_string(),
+            token: None,
+            address: HumanAddr::from("secret007"),
+            key: "user_key".to_string(),
+        });
+        assert!(res.is_ok());
+
+        let _res = handle(&mut deps, env, HandleMsg::DeregisterUser { cred_id: "cred1".to_string() }).unwrap();
+        assert_eq!(by_alias(&deps), vec!["cred2".to_string()]);
+        assert_eq!(by_address(&deps), vec!["cred2".to_string()]);
     }
 }
@@ -0,0 +1,94 @@
+use std::convert::TryFrom;
+
+use cosmwasm_std::{Binary, StdError, StdResult};
+use k256::ecdsa::signature::Verifier;
+use k256::ecdsa::{Signature, VerifyingKey};
+use ripemd160::Ripemd160;
+use sha2::{Digest, Sha256};
+
+pub fn sha_256(data: &[u8]) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&Sha256::digest(data));
+    hash
+}
+
+pub fn ripemd_160(data: &[u8]) -> [u8; 20] {
+    let mut hash = [0u8; 20];
+    hash.copy_from_slice(&Ripemd160::digest(data));
+    hash
+}
+
+// verifies a 64 byte (r, s) secp256k1 signature over the sha256 hash of message,
+// pubkey is a compressed or uncompressed SEC1 public key
+pub fn verify_signature(message: &[u8], signature: &[u8], pubkey: &[u8]) -> StdResult<bool> {
+    let pubkey = VerifyingKey::from_sec1_bytes(pubkey)
+        .map_err(|_| StdError::generic_err("Invalid public key"))?;
+    let signature = Signature::try_from(signature)
+        .map_err(|_| StdError::generic_err("Invalid signature format"))?;
+
+    Ok(pubkey.verify(message, &signature).is_ok())
+}
+
+// checks leaf is in the tree with root, pairs are hashed in sorted order so
+// the proof is just the sibling hashes from the leaf up
+pub fn verify_merkle_proof(root: &[u8], leaf: [u8; 32], proof: &[Binary]) -> bool {
+    let mut hash = leaf;
+    for sibling in proof {
+        let sibling = sibling.as_slice();
+        let mut pair = Vec::with_capacity(hash.len() + sibling.len());
+        if hash.as_ref() <= sibling {
+            pair.extend_from_slice(&hash);
+            pair.extend_from_slice(sibling);
+        } else {
+            pair.extend_from_slice(sibling);
+            pair.extend_from_slice(&hash);
+        }
+        hash = sha_256(&pair);
+    }
+    hash.as_ref() == root
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use k256::ecdsa::signature::Signer;
+    use k256::ecdsa::SigningKey;
+
+    #[test]
+    fn verify_signature_works() {
+        let signing_key = SigningKey::from_bytes(&[1u8; 32]).unwrap();
+        let pubkey = signing_key.verify_key().to_bytes();
+        let signature: Signature = signing_key.sign(b"message");
+
+        assert_eq!(verify_signature(b"message", signature.as_ref(), &pubkey), Ok(true));
+        assert_eq!(verify_signature(b"tampered", signature.as_ref(), &pubkey), Ok(false));
+
+        match verify_signature(b"message", &[0u8; 10], &pubkey) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Invalid signature format"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match verify_signature(b"message", signature.as_ref(), &[0u8; 33]) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Invalid public key"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    fn hash_pair(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+        let (first, second) = if a <= b { (a, b) } else { (b, a) };
+        sha_256(&[first.as_ref(), second.as_ref()].concat())
+    }
+
+    #[test]
+    fn verify_merkle_proof_works() {
+        let leaves = [sha_256(b"a"), sha_256(b"b"), sha_256(b"c")];
+        let left = hash_pair(&leaves[0], &leaves[1]);
+        let root = hash_pair(&left, &leaves[2]);
+
+        let proof = vec![Binary(leaves[1].to_vec()), Binary(leaves[2].to_vec())];
+        assert!(verify_merkle_proof(&root, leaves[0], &proof));
+        assert!(verify_merkle_proof(&root, leaves[2], &[Binary(left.to_vec())]));
+
+        assert!(!verify_merkle_proof(&root, sha_256(b"d"), &proof));
+        assert!(!verify_merkle_proof(&root, leaves[0], &proof[..1]));
+    }
+}
@@ -1,8 +1,12 @@
 pub mod contract;
+pub mod crypto;
 pub mod msg;
+pub mod permit;
+pub mod snip20;
 pub mod state;
 pub mod coin_helpers;
 pub mod tokens;
+pub mod viewing_key;
 
 #[cfg(target_arch = "wasm32")]
 mod wasm {
@@ -1,11 +1,19 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use cosmwasm_std::{CanonicalAddr, HumanAddr, Uint128};
-use crate::state::{PolicyType, ContractInfo};
+use cosmwasm_std::{Binary, CanonicalAddr, HumanAddr, StdError, StdResult, Uint128};
+use crate::state::{Allocation, Caps, Uniqueness, ClaimMode, ContractStatus, Distribution, MerkleRoot, PayoutMode, PolicyType, ContractInfo, Revocation, RewardToken, Role, TokenAmount, TokenBalance, VestingTerms};
+use crate::permit::Permit;
+use crate::viewing_key::ViewingKey;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InitMsg {
     pub token_contract: ContractInfo,
+    pub claim_mode: Option<ClaimMode>,  // defaults to ClaimMode::Push
+    pub payout_mode: Option<PayoutMode>,  // defaults to PayoutMode::Mint
+    pub caps: Option<Caps>,  // defaults to no caps
+    pub balanced_vesting: Option<VestingTerms>,  // defaults to paying Balanced allocations right away
+    pub uniqueness: Option<Uniqueness>,  // defaults to unique addresses and aliases
+    pub prng_seed: Binary,  // secret seed for generating viewing keys
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -16,23 +24,274 @@ pub enum HandleMsg {
         amount: Uint128,
         cred_id: String,
         policy_type: PolicyType,
+        token: Option<HumanAddr>,  // reward token to pay in, defaults to token_contract
     },
     RegisterUser {
         cred_id: String,
         scrt_address: HumanAddr,
         alias: Option<String>,
     },
-//todo handle deregister and update?
+    AllocateBatch {
+        allocation_id: String,
+        policy_type: PolicyType,
+        receipts: Vec<Receipt>,
+        mode: BatchMode,
+        token: Option<HumanAddr>,
+    },
+    // cred_id picks one of the creds registered to the sender, needed when
+    // there are several
+    Claim {
+        amount: Option<Uint128>,
+        token: Option<HumanAddr>,
+        cred_id: Option<String>,
+    },
+    SetClaimMode {
+        claim_mode: ClaimMode,
+    },
+    MigrateAllocations {
+        cred_ids: Vec<String>,
+    },
+    ProposeOwner {
+        address: HumanAddr,
+    },
+    AcceptOwnership {},
+    CancelOwnershipProposal {},
+    GrantRole {
+        role: Role,
+        address: HumanAddr,
+    },
+    RevokeRole {
+        role: Role,
+        address: HumanAddr,
+    },
+    UpdateUserAddress {
+        cred_id: String,
+        scrt_address: HumanAddr,
+    },
+    DeregisterUser {
+        cred_id: String,
+    },
+    SetRegistrarKey {
+        pubkey: Option<Binary>,
+    },
+    SetUniqueness {
+        uniqueness: Uniqueness,
+    },
+    SetOracleKey {
+        pubkey: Option<Binary>,
+    },
+    SubmitSignedAllocations {
+        payload: SignedAllocations,
+        signature: Binary,
+    },
+    ClaimIdentity {
+        cred_id: String,
+        alias: Option<String>,
+        expiry: u64,
+        signature: Binary,
+    },
+    CreateViewingKey {
+        entropy: String,
+        padding: Option<String>,
+    },
+    SetViewingKey {
+        key: String,
+        padding: Option<String>,
+    },
+    RevokePermit {
+        permit_name: String,
+        padding: Option<String>,
+    },
+    AddRewardToken {
+        token: ContractInfo,
+    },
+    SetStatus {
+        status: ContractStatus,
+    },
+    SetCaps {
+        caps: Caps,
+        token: Option<HumanAddr>,  // reward token the caps apply to, defaults to token_contract
+    },
+    SetBalancedVesting {
+        terms: Option<VestingTerms>,
+    },
+    RevokeAllocation {
+        cred_id: String,
+        allocation_id: String,
+        reason: String,
+    },
+    PublishDistributionRoot {
+        distribution_id: String,
+        merkle_root: Binary,
+        total: Uint128,
+        token: Option<HumanAddr>,
+    },
+    // the sender's cred_id and amount must be a leaf of the distribution's root,
+    // cred_id picks one of the sender's creds like in Claim
+    ClaimFromRoot {
+        distribution_id: String,
+        amount: Uint128,
+        proof: Vec<Binary>,
+        cred_id: Option<String>,
+    },
+    CreateDistribution {
+        id: String,
+        policy: PolicyType,
+        budget: Uint128,
+        timestamp: u64,
+        token: Option<HumanAddr>,
+    },
+    FinalizeDistribution {
+        id: String,
+    },
+    // funds the native treasury with the uscrt sent along
+    Fund {
+        amount: Uint128,
+    },
+    // SNIP-20 callback when tokens are sent to the contract
+    Receive {
+        sender: HumanAddr,
+        from: HumanAddr,
+        amount: Uint128,
+        memo: Option<String>,
+        msg: Option<Binary>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HandleAnswer {
+    CreateViewingKey { key: ViewingKey },
+}
+
+// signed off-chain by the registrar key to let scrt_address register cred_id
+// with this contract itself until expiry (block time in seconds), registrations
+// is how many times cred_id has registered before, so an attestation can't be
+// used again after deregistering, the signed message is the JSON
+// {"contract_address":"secret1...","cred_id":"...","scrt_address":"secret1...","registrations":0,"expiry":1600000000}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct IdentityAttestation {
+    pub contract_address: HumanAddr,
+    pub cred_id: String,
+    pub scrt_address: HumanAddr,
+    pub registrations: u64,
+    pub expiry: u64,
+}
+
+// a batch allocation signed off-chain by the oracle key, the signed message is
+// the JSON of this struct with its fields in this order, nonce has to be
+// greater than that of the last payload accepted
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SignedAllocations {
+    pub contract_address: HumanAddr,
+    pub nonce: u64,
+    pub allocation_id: String,
+    pub policy_type: PolicyType,
+    pub receipts: Vec<Receipt>,
+    pub mode: BatchMode,
+    pub token: Option<HumanAddr>,
+}
+
+// a single cred_id's share of a batch allocation, or of a distribution root
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Receipt {
+    pub cred_id: String,
+    pub amount: Uint128,
+}
+
+// how a batch treats receipts that were already allocated, a cred listed
+// twice in one batch fails it either way
+// Skip -- leave them out and allocate the rest
+// Reject -- fail the whole batch
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchMode {
+    Skip,
+    Reject,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
     Config {},
-    GetTotalAllocated { cred_id: String},
     IsCredRegistered { cred_id: String},
+    PendingOwner {},
+    RoleHolders { role: Role },
+    Treasury { token: Option<HumanAddr> },
+    RewardTokens {},
+    ContractStatus {},
+    Distribution { id: String },
+    CredIdByAlias { alias: String },
+    DistributionRoot { distribution_id: String },
+    // time is the block time to count the window back from, queries don't know it,
+    // per_user is for cred_id, or for a cred with nothing allocated yet without it
+    CapHeadroom { allocation_id: String, cred_id: Option<String>, time: u64, token: Option<HumanAddr> },
+
+    // per-user queries, authenticated with the viewing key of address, which
+    // must be registered to cred_id or hold the registrar or allocator role,
+    // token defaults to token_contract
+    GetTotalAllocated { cred_id: String, token: Option<HumanAddr>, address: HumanAddr, key: String },
+    IsAllocated { cred_id: String, allocation_id: String, address: HumanAddr, key: String },
+    GetUserCred { cred_id: String, address: HumanAddr, key: String },
+    GetEscrow { cred_id: String, address: HumanAddr, key: String },
+    GetClaimable { cred_id: String, token: Option<HumanAddr>, address: HumanAddr, key: String },
+    // time is the block time to compute vested amounts at
+    GetVesting { cred_id: String, time: u64, address: HumanAddr, key: String },
+    // revocations of cred_id, oldest first, start_after is the position of
+    // the last one on the previous page
+    GetRevocations { cred_id: String, start_after: Option<u64>, limit: Option<u32>, address: HumanAddr, key: String },
+    // the cred_ids registered to address itself
+    CredIdByAddress { address: HumanAddr, key: String },
+    // allocations of cred_id in the order they were made, start_after is the
+    // position of the last one on the previous page, policy filters by kind
+    // ignoring the policy's parameters
+    GetAllocations {
+        cred_id: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+        policy: Option<PolicyType>,
+        address: HumanAddr,
+        key: String,
+    },
+    // registered users in registration order, for the registrar and allocator
+    // roles, start_after is the position of the last user of the previous page
+    ListUsers { start_after: Option<u64>, limit: Option<u32>, address: HumanAddr, key: String },
+
+    // the same queries authenticated with a permit signed by the address
+    WithPermit { permit: Permit, query: QueryWithPermit },
+}
+
+impl QueryMsg {
+    pub fn get_validation_params(&self) -> StdResult<(&HumanAddr, ViewingKey)> {
+        match self {
+            Self::GetTotalAllocated { address, key, .. } => Ok((address, ViewingKey(key.clone()))),
+            Self::IsAllocated { address, key, .. } => Ok((address, ViewingKey(key.clone()))),
+            Self::GetUserCred { address, key, .. } => Ok((address, ViewingKey(key.clone()))),
+            Self::GetEscrow { address, key, .. } => Ok((address, ViewingKey(key.clone()))),
+            Self::GetClaimable { address, key, .. } => Ok((address, ViewingKey(key.clone()))),
+            Self::GetVesting { address, key, .. } => Ok((address, ViewingKey(key.clone()))),
+            Self::GetRevocations { address, key, .. } => Ok((address, ViewingKey(key.clone()))),
+            Self::CredIdByAddress { address, key } => Ok((address, ViewingKey(key.clone()))),
+            Self::GetAllocations { address, key, .. } => Ok((address, ViewingKey(key.clone()))),
+            Self::ListUsers { address, key, .. } => Ok((address, ViewingKey(key.clone()))),
+            _ => Err(StdError::generic_err("This query type does not require authentication")),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryWithPermit {
+    GetTotalAllocated { cred_id: String, token: Option<HumanAddr> },
     IsAllocated { cred_id: String, allocation_id: String },
-    GetUserCred { cred_id: String},
+    GetUserCred { cred_id: String },
+    GetEscrow { cred_id: String },
+    GetClaimable { cred_id: String, token: Option<HumanAddr> },
+    GetVesting { cred_id: String, time: u64 },
+    GetRevocations { cred_id: String, start_after: Option<u64>, limit: Option<u32> },
+    CredIdByAddress {},
+    GetAllocations { cred_id: String, start_after: Option<u64>, limit: Option<u32>, policy: Option<PolicyType> },
+    ListUsers { start_after: Option<u64>, limit: Option<u32> },
 }
 
 // We define a custom struct for each query response
@@ -44,6 +303,7 @@ pub struct TotalAllocatedResponse {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct CredRegisteredResponse {
     pub registered: bool,
+    pub registrations: u64,  // times cred_id has registered, as signed into identity attestations
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -55,4 +315,128 @@ pub struct CredAllocatedResponse {
 pub struct UserCredResponse {
     pub scrt_address: CanonicalAddr,
     pub total_allocated: Uint128,
+    pub token_balances: Vec<TokenBalance>,  // reward tokens besides the default one
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct EscrowResponse {
+    pub total_escrowed: Uint128,
+    pub tokens: Vec<TokenAmount>,  // reward tokens besides the default one
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClaimableResponse {
+    pub claimable: Uint128,
+    pub claimed: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct VestingResponse {
+    pub schedules: Vec<VestingStatus>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct VestingStatus {
+    pub allocation_id: String,
+    pub token: Option<HumanAddr>,
+    pub amount: Uint128,
+    pub vested: Uint128,
+    pub unvested: Uint128,
+    pub claimed: Uint128,  // released to the claimable balance by claiming
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RevocationsResponse {
+    pub revocations: Vec<RevocationRecord>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RevocationRecord {
+    pub position: u64,
+    pub revocation: Revocation,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AllocationsResponse {
+    pub allocations: Vec<AllocationRecord>,
+    pub next_start_after: Option<u64>,  // where the next page starts, None once the index is exhausted
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AllocationRecord {
+    pub position: u64,
+    pub allocation: Allocation,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListUsersResponse {
+    pub users: Vec<UserInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UserInfo {
+    pub position: u64,
+    pub cred_id: String,
+    pub alias: Option<String>,
+    pub scrt_address: HumanAddr,
+    pub total_allocated: Uint128,
+    pub claimable: Uint128,
+    pub claimed: Uint128,
+    pub token_balances: Vec<TokenBalance>,  // reward tokens besides the default one
+}
+
+// at most one cred_id for an address, and for an alias while aliases are unique
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CredIdsResponse {
+    pub cred_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingOwnerResponse {
+    pub pending_owner: Option<HumanAddr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RoleHoldersResponse {
+    pub role: Role,
+    pub holders: Vec<HumanAddr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TreasuryResponse {
+    pub payout_mode: PayoutMode,
+    pub balance: Uint128,
+    pub liabilities: Uint128,  // owed to escrowed, claimable and vesting balances
+    pub native_balance: Option<Uint128>,  // uscrt held by the contract, only in PayoutMode::Native
+}
+
+// totals of every reward token, the default token first
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RewardTokensResponse {
+    pub tokens: Vec<RewardToken>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ContractStatusResponse {
+    pub status: ContractStatus,
+}
+
+// how much more can be allocated before each cap is hit, None for caps that are off
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CapHeadroomResponse {
+    pub per_allocation: Option<Uint128>,
+    pub per_user: Option<Uint128>,
+    pub window: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DistributionResponse {
+    pub distribution: Distribution,
+    pub remaining: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DistributionRootResponse {
+    pub root: MerkleRoot,
+    pub remaining: Uint128,
 }
@@ -0,0 +1,182 @@
+use bech32::ToBase32;
+use cosmwasm_std::{to_vec, Api, Binary, Extern, HumanAddr, Querier, StdError, StdResult, Storage, Uint128};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{ripemd_160, sha_256, verify_signature};
+use crate::state::revoked_permits_read;
+
+pub const BECH32_PREFIX: &str = "secret";
+
+// SNIP-24 query permit, signed offline by the querying address
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Permit {
+    pub params: PermitParams,
+    pub signature: PermitSignature,
+}
+
+// allowed_tokens keeps the SNIP-24 name wallets sign with, here it lists the
+// cred contracts the permit is valid for
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PermitParams {
+    pub allowed_tokens: Vec<HumanAddr>,
+    pub permit_name: String,
+    pub chain_id: String,
+    pub permissions: Vec<Permission>,
+}
+
+// Cred -- cred balances, escrow and claimable amounts
+// Allocations -- which allocations a cred received
+// Owner -- everything the address could read with a viewing key
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Cred,
+    Allocations,
+    Owner,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PermitSignature {
+    pub pub_key: PubKey,
+    pub signature: Binary,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PubKey {
+    // always "tendermint/PubKeySecp256k1"
+    pub r#type: String,
+    pub value: Binary,
+}
+
+// the amino sign doc wallets sign for a permit, fields are declared in
+// alphabetical order so serializing gives the sorted JSON that was signed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SignedPermit {
+    pub account_number: Uint128,
+    pub chain_id: String,
+    pub fee: Fee,
+    pub memo: String,
+    pub msgs: Vec<PermitMsg>,
+    pub sequence: Uint128,
+}
+
+impl SignedPermit {
+    pub fn from_params(params: &PermitParams) -> Self {
+        Self {
+            account_number: Uint128::zero(),
+            chain_id: params.chain_id.clone(),
+            fee: Fee::new(),
+            memo: String::new(),
+            msgs: vec![PermitMsg::from_params(params)],
+            sequence: Uint128::zero(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Fee {
+    pub amount: Vec<FeeCoin>,
+    pub gas: Uint128,
+}
+
+impl Fee {
+    pub fn new() -> Self {
+        Self {
+            amount: vec![FeeCoin { amount: Uint128::zero(), denom: "uscrt".to_string() }],
+            gas: Uint128(1),
+        }
+    }
+}
+
+impl Default for Fee {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct FeeCoin {
+    pub amount: Uint128,
+    pub denom: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PermitMsg {
+    pub r#type: String,
+    pub value: PermitContent,
+}
+
+impl PermitMsg {
+    pub fn from_params(params: &PermitParams) -> Self {
+        Self {
+            r#type: "query_permit".to_string(),
+            value: PermitContent {
+                allowed_tokens: params.allowed_tokens.clone(),
+                permissions: params.permissions.clone(),
+                permit_name: params.permit_name.clone(),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PermitContent {
+    pub allowed_tokens: Vec<HumanAddr>,
+    pub permissions: Vec<Permission>,
+    pub permit_name: String,
+}
+
+// bech32 address of a compressed secp256k1 public key
+pub fn pubkey_to_address(pubkey: &[u8]) -> StdResult<HumanAddr> {
+    let raw = ripemd_160(&sha_256(pubkey));
+    let address = bech32::encode(BECH32_PREFIX, raw.to_base32())
+        .map_err(|err| StdError::generic_err(format!("Failed to encode address: {}", err)))?;
+
+    Ok(HumanAddr(address))
+}
+
+// checks the permit is for this contract, not revoked and signed by the key
+// it carries, returning the address that signed it
+pub fn validate<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    permit: &Permit,
+    contract_address: &HumanAddr,
+) -> StdResult<HumanAddr> {
+    if !permit.params.allowed_tokens.contains(contract_address) {
+        return Err(StdError::generic_err(format!(
+            "Permit doesn't apply to contract {}, allowed contracts: {:?}",
+            contract_address, permit.params.allowed_tokens
+        )));
+    }
+
+    let pubkey = permit.signature.pub_key.value.as_slice();
+    let address = pubkey_to_address(pubkey)?;
+
+    let address_raw = deps.api.canonical_address(&address)?;
+    if revoked_permits_read(&deps.storage, &address_raw)
+        .may_load(permit.params.permit_name.as_bytes())?
+        .is_some()
+    {
+        return Err(StdError::generic_err(format!(
+            "Permit {:?} was revoked by account {:?}",
+            permit.params.permit_name, address
+        )));
+    }
+
+    let signed_bytes = to_vec(&SignedPermit::from_params(&permit.params))?;
+    if !verify_signature(&signed_bytes, permit.signature.signature.as_slice(), pubkey)? {
+        return Err(StdError::generic_err("Failed to verify signatures for the given permit"));
+    }
+
+    Ok(address)
+}
@@ -0,0 +1,203 @@
+use cosmwasm_std::{to_binary, Binary, CosmosMsg, HumanAddr, StdResult, Uint128, WasmMsg};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::state::ContractInfo;
+
+// messages are padded with spaces to a multiple of this, so their length
+// doesn't reveal the amounts or addresses inside
+pub const BLOCK_SIZE: usize = 256;
+
+// the SNIP-20 handle messages this contract sends to its token
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HandleMsg {
+    Mint {
+        recipient: HumanAddr,
+        amount: Uint128,
+        memo: Option<String>,
+        padding: Option<String>,
+    },
+    Transfer {
+        recipient: HumanAddr,
+        amount: Uint128,
+        memo: Option<String>,
+        padding: Option<String>,
+    },
+    Send {
+        recipient: HumanAddr,
+        recipient_code_hash: Option<String>,
+        amount: Uint128,
+        msg: Option<Binary>,
+        memo: Option<String>,
+        padding: Option<String>,
+    },
+    BatchMint {
+        actions: Vec<MintAction>,
+        padding: Option<String>,
+    },
+    BatchTransfer {
+        actions: Vec<TransferAction>,
+        padding: Option<String>,
+    },
+    RegisterReceive {
+        code_hash: String,
+        padding: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MintAction {
+    pub recipient: HumanAddr,
+    pub amount: Uint128,
+    pub memo: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TransferAction {
+    pub recipient: HumanAddr,
+    pub amount: Uint128,
+    pub memo: Option<String>,
+}
+
+impl HandleMsg {
+    pub fn to_cosmos_msg(&self, block_size: usize, token: &ContractInfo) -> StdResult<CosmosMsg> {
+        let mut msg = to_binary(self)?;
+        space_pad(block_size, &mut msg.0);
+
+        Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: token.address.clone(),
+            callback_code_hash: token.code_hash.clone(),
+            msg,
+            send: vec![],
+        }))
+    }
+}
+
+// pads message with trailing spaces to a multiple of block_size, a block_size
+// of zero leaves it as is
+pub fn space_pad(block_size: usize, message: &mut Vec<u8>) {
+    if block_size == 0 {
+        return;
+    }
+    let surplus = message.len() % block_size;
+    if surplus == 0 {
+        return;
+    }
+
+    let missing = block_size - surplus;
+    message.resize(message.len() + missing, b' ');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn token() -> ContractInfo {
+        ContractInfo {
+            code_hash: "tokenhash".to_string(),
+            address: HumanAddr::from("token"),
+        }
+    }
+
+    fn unwrap_msg(msg: CosmosMsg) -> Vec<u8> {
+        match msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, callback_code_hash, msg, send }) => {
+                assert_eq!(contract_addr, HumanAddr::from("token"));
+                assert_eq!(callback_code_hash, "tokenhash");
+                assert!(send.is_empty());
+                msg.0
+            }
+            _ => panic!("expected a wasm execute message"),
+        }
+    }
+
+    #[test]
+    fn messages_serialize_to_snip20() {
+        let msg = HandleMsg::Mint {
+            recipient: HumanAddr::from("secret007"),
+            amount: Uint128(100),
+            memo: Some("say \"hi\"".to_string()),
+            padding: None,
+        };
+        assert_eq!(
+            String::from_utf8(unwrap_msg(msg.to_cosmos_msg(0, &token()).unwrap())).unwrap(),
+            r#"{"mint":{"recipient":"secret007","amount":"100","memo":"say \"hi\"","padding":null}}"#
+        );
+
+        let msg = HandleMsg::Transfer {
+            recipient: HumanAddr::from("secret007"),
+            amount: Uint128(100),
+            memo: None,
+            padding: None,
+        };
+        assert_eq!(
+            String::from_utf8(unwrap_msg(msg.to_cosmos_msg(0, &token()).unwrap())).unwrap(),
+            r#"{"transfer":{"recipient":"secret007","amount":"100","memo":null,"padding":null}}"#
+        );
+
+        let msg = HandleMsg::Send {
+            recipient: HumanAddr::from("pool"),
+            recipient_code_hash: Some("poolhash".to_string()),
+            amount: Uint128(5),
+            msg: Some(Binary(b"{}".to_vec())),
+            memo: None,
+            padding: None,
+        };
+        assert_eq!(
+            String::from_utf8(unwrap_msg(msg.to_cosmos_msg(0, &token()).unwrap())).unwrap(),
+            r#"{"send":{"recipient":"pool","recipient_code_hash":"poolhash","amount":"5","msg":"e30=","memo":null,"padding":null}}"#
+        );
+
+        let msg = HandleMsg::BatchMint {
+            actions: vec![
+                MintAction { recipient: HumanAddr::from("secret007"), amount: Uint128(1), memo: None },
+                MintAction { recipient: HumanAddr::from("secret008"), amount: Uint128(2), memo: Some("alloc1".to_string()) },
+            ],
+            padding: None,
+        };
+        assert_eq!(
+            String::from_utf8(unwrap_msg(msg.to_cosmos_msg(0, &token()).unwrap())).unwrap(),
+            concat!(
+                r#"{"batch_mint":{"actions":["#,
+                r#"{"recipient":"secret007","amount":"1","memo":null},"#,
+                r#"{"recipient":"secret008","amount":"2","memo":"alloc1"}"#,
+                r#"],"padding":null}}"#
+            )
+        );
+
+        let msg = HandleMsg::BatchTransfer {
+            actions: vec![TransferAction { recipient: HumanAddr::from("secret007"), amount: Uint128(1), memo: None }],
+            padding: None,
+        };
+        assert_eq!(
+            String::from_utf8(unwrap_msg(msg.to_cosmos_msg(0, &token()).unwrap())).unwrap(),
+            r#"{"batch_transfer":{"actions":[{"recipient":"secret007","amount":"1","memo":null}],"padding":null}}"#
+        );
+
+        let msg = HandleMsg::RegisterReceive { code_hash: "credhash".to_string(), padding: None };
+        assert_eq!(
+            String::from_utf8(unwrap_msg(msg.to_cosmos_msg(0, &token()).unwrap())).unwrap(),
+            r#"{"register_receive":{"code_hash":"credhash","padding":null}}"#
+        );
+    }
+
+    #[test]
+    fn messages_are_padded_to_block_size() {
+        let msg = HandleMsg::Mint {
+            recipient: HumanAddr::from("secret007"),
+            amount: Uint128(100),
+            memo: None,
+            padding: None,
+        };
+        let unpadded = r#"{"mint":{"recipient":"secret007","amount":"100","memo":null,"padding":null}}"#;
+
+        let padded = unwrap_msg(msg.to_cosmos_msg(BLOCK_SIZE, &token()).unwrap());
+        assert_eq!(padded.len(), BLOCK_SIZE);
+        assert_eq!(String::from_utf8(padded).unwrap().trim_end(), unpadded);
+
+        let mut exact = vec![b'x'; BLOCK_SIZE];
+        space_pad(BLOCK_SIZE, &mut exact);
+        assert_eq!(exact.len(), BLOCK_SIZE);
+    }
+}
@@ -5,17 +5,312 @@ use cosmwasm_storage::{
     bucket, bucket_read, singleton, singleton_read, Bucket, ReadonlyBucket, ReadonlySingleton,
     Singleton,
 };
-use cosmwasm_std::{HumanAddr, CanonicalAddr, Storage, Uint128};
+use cosmwasm_std::{Binary, Decimal, HumanAddr, CanonicalAddr, StdError, StdResult, Storage, Uint128};
 
 pub static CONFIG_KEY: &[u8] = b"config";
 pub static USER_CRED_KEY: &[u8] = b"user_cred";
+pub static DEREGISTERED_CRED_KEY: &[u8] = b"deregistered_cred";
+pub static REGISTRATIONS_KEY: &[u8] = b"registrations";
+pub static ESCROW_KEY: &[u8] = b"escrow";
+pub static ADDRESS_CRED_KEY: &[u8] = b"address_cred";
+pub static ALLOCATIONS_KEY: &[u8] = b"allocations";
+pub static ROLES_KEY: &[u8] = b"roles";
+pub static PRNG_SEED_KEY: &[u8] = b"prng_seed";
+pub static VIEWING_KEY_KEY: &[u8] = b"viewing_key";
+pub static REVOKED_PERMITS_KEY: &[u8] = b"revoked_permits";
+pub static ALLOCATION_TOTALS_KEY: &[u8] = b"allocation_totals";
+pub static TOKEN_ALLOCATION_TOTALS_KEY: &[u8] = b"token_allocation_totals";
+pub static CAP_WINDOW_KEY: &[u8] = b"cap_window";
+pub static DISTRIBUTIONS_KEY: &[u8] = b"distributions";
+pub static VESTING_KEY: &[u8] = b"vesting";
+pub static REVOCATIONS_KEY: &[u8] = b"revocations";
+pub static REVOCATIONS_INDEXED_KEY: &[u8] = b"revocations_indexed";
+pub static MERKLE_ROOTS_KEY: &[u8] = b"merkle_roots";
+pub static ROOT_CLAIMS_KEY: &[u8] = b"root_claims";
+pub static USER_INDEX_KEY: &[u8] = b"user_index";
+pub static USER_POSITION_KEY: &[u8] = b"user_position";
+pub static ALLOCATION_INDEX_KEY: &[u8] = b"allocation_index";
+pub static ALLOCATION_POSITION_KEY: &[u8] = b"allocation_position";
+pub static ALLOCATIONS_INDEXED_KEY: &[u8] = b"allocations_indexed";
+pub static ALIAS_CRED_KEY: &[u8] = b"alias_cred";
 
+// fields added since the first release default, so its stored State still
+// loads and MigrateAllocations can run
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct State {
     pub total_cred: Uint128,
     pub total_users: u64,
+    #[serde(default)]
+    pub users_indexed: u64,  // entries in user_index, deregistered ones included
+    #[serde(default)]
+    pub total_escrowed: Uint128,
+    #[serde(default)]
+    pub total_unclaimed: Uint128,
+    #[serde(default)]
+    pub total_vesting: Uint128,  // allocated but not released by a vesting schedule yet
     pub owner: CanonicalAddr,
-    pub token_contract: ContractInfo,
+    #[serde(default)]
+    pub pending_owner: Option<CanonicalAddr>,  // proposed owner, until they accept
+    #[serde(default)]
+    pub registrar_pubkey: Option<Binary>,  // secp256k1 key signing identity attestations
+    #[serde(default)]
+    pub oracle_pubkey: Option<Binary>,  // secp256k1 key signing allocations anyone can submit
+    #[serde(default)]
+    pub oracle_nonce: u64,  // nonce of the last signed allocations accepted
+    pub token_contract: ContractInfo,  // default reward token, its totals are the ones above
+    #[serde(default)]
+    pub reward_tokens: Vec<RewardToken>,  // further reward tokens allocations can pay in
+    #[serde(default = "first_release_claim_mode")]
+    pub claim_mode: ClaimMode,
+    #[serde(default = "first_release_payout_mode")]
+    pub payout_mode: PayoutMode,
+    #[serde(default)]
+    pub treasury_balance: Uint128,  // funds held for payouts, only used in PayoutMode::Transfer and PayoutMode::Native
+    #[serde(default)]
+    pub contract_address: HumanAddr,  // checked against query permits, which have no env
+    #[serde(default = "first_release_status")]
+    pub status: ContractStatus,
+    #[serde(default)]
+    pub caps: Caps,  // caps on allocations in the default token
+    #[serde(default)]
+    pub balanced_vesting: Option<VestingTerms>,  // terms PolicyType::Balanced vests on, None pays it right away
+    #[serde(default)]
+    pub policy_totals: Vec<PolicyTotal>,  // allocated under each policy in the default token
+    #[serde(default)]
+    pub uniqueness: Uniqueness,
+}
+
+// how the first release, which had no modes or status, behaved
+fn first_release_claim_mode() -> ClaimMode {
+    ClaimMode::Push
+}
+
+fn first_release_payout_mode() -> PayoutMode {
+    PayoutMode::Mint
+}
+
+fn first_release_status() -> ContractStatus {
+    ContractStatus::Normal
+}
+
+impl State {
+    // the registry key of a reward token, None for the default token
+    pub fn token_key(&self, token: Option<HumanAddr>) -> StdResult<Option<HumanAddr>> {
+        match token {
+            None => Ok(None),
+            Some(token) if token == self.token_contract.address => Ok(None),
+            Some(token) => {
+                if self.reward_tokens.iter().any(|reward| reward.contract.address == token) {
+                    Ok(Some(token))
+                } else {
+                    Err(StdError::generic_err(format!("Unknown reward token: {}", token)))
+                }
+            }
+        }
+    }
+
+    pub fn token_contract(&self, token: &Option<HumanAddr>) -> StdResult<&ContractInfo> {
+        match token {
+            None => Ok(&self.token_contract),
+            Some(token) => Ok(&self.reward_token(token)?.contract),
+        }
+    }
+
+    pub fn totals(&self, token: &Option<HumanAddr>) -> StdResult<TokenTotals> {
+        match token {
+            None => Ok(TokenTotals {
+                total_cred: self.total_cred,
+                total_escrowed: self.total_escrowed,
+                total_unclaimed: self.total_unclaimed,
+                total_vesting: self.total_vesting,
+                treasury_balance: self.treasury_balance,
+                policy_totals: self.policy_totals.clone(),
+            }),
+            Some(token) => Ok(self.reward_token(token)?.totals.clone()),
+        }
+    }
+
+    pub fn set_totals(&mut self, token: &Option<HumanAddr>, totals: TokenTotals) -> StdResult<()> {
+        match token {
+            None => {
+                self.total_cred = totals.total_cred;
+                self.total_escrowed = totals.total_escrowed;
+                self.total_unclaimed = totals.total_unclaimed;
+                self.total_vesting = totals.total_vesting;
+                self.treasury_balance = totals.treasury_balance;
+                self.policy_totals = totals.policy_totals;
+            }
+            Some(token) => {
+                let reward = self.reward_tokens
+                    .iter_mut()
+                    .find(|reward| &reward.contract.address == token)
+                    .ok_or_else(|| StdError::generic_err(format!("Unknown reward token: {}", token)))?;
+                reward.totals = totals;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn caps(&self, token: &Option<HumanAddr>) -> StdResult<&Caps> {
+        match token {
+            None => Ok(&self.caps),
+            Some(token) => Ok(&self.reward_token(token)?.caps),
+        }
+    }
+
+    pub fn set_caps(&mut self, token: &Option<HumanAddr>, caps: Caps) -> StdResult<()> {
+        match token {
+            None => self.caps = caps,
+            Some(token) => {
+                let reward = self.reward_tokens
+                    .iter_mut()
+                    .find(|reward| &reward.contract.address == token)
+                    .ok_or_else(|| StdError::generic_err(format!("Unknown reward token: {}", token)))?;
+                reward.caps = caps;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn update_totals<F>(&mut self, token: &Option<HumanAddr>, update: F) -> StdResult<()>
+    where
+        F: FnOnce(&mut TokenTotals) -> StdResult<()>,
+    {
+        let mut totals = self.totals(token)?;
+        update(&mut totals)?;
+        self.set_totals(token, totals)
+    }
+
+    pub fn add_policy_total(&mut self, token: &Option<HumanAddr>, policy: &PolicyType, amount: Uint128) -> StdResult<()> {
+        self.update_totals(token, |totals| {
+            match totals.policy_totals.iter_mut().find(|total| total.policy == policy.as_str()) {
+                Some(total) => total.total += amount,
+                None => totals.policy_totals.push(PolicyTotal { policy: policy.as_str().to_string(), total: amount }),
+            }
+            Ok(())
+        })
+    }
+
+    pub fn sub_policy_total(&mut self, token: &Option<HumanAddr>, policy: &PolicyType, amount: Uint128) -> StdResult<()> {
+        self.update_totals(token, |totals| {
+            if let Some(total) = totals.policy_totals.iter_mut().find(|total| total.policy == policy.as_str()) {
+                total.total = (total.total - amount)?;
+            }
+            Ok(())
+        })
+    }
+
+    // every token, the default one first
+    pub fn token_keys(&self) -> Vec<Option<HumanAddr>> {
+        let mut keys = vec![None];
+        keys.extend(self.reward_tokens.iter().map(|reward| Some(reward.contract.address.clone())));
+        keys
+    }
+
+    fn reward_token(&self, token: &HumanAddr) -> StdResult<&RewardToken> {
+        self.reward_tokens
+            .iter()
+            .find(|reward| &reward.contract.address == token)
+            .ok_or_else(|| StdError::generic_err(format!("Unknown reward token: {}", token)))
+    }
+}
+
+// a reward token besides State.token_contract
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RewardToken {
+    pub contract: ContractInfo,
+    pub totals: TokenTotals,
+    #[serde(default)]
+    pub caps: Caps,
+}
+
+// total allocated under a policy, keyed by PolicyType::as_str
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PolicyTotal {
+    pub policy: String,
+    pub total: Uint128,
+}
+
+// the State totals kept for each reward token
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct TokenTotals {
+    pub total_cred: Uint128,
+    pub total_escrowed: Uint128,
+    pub total_unclaimed: Uint128,
+    pub total_vesting: Uint128,
+    pub treasury_balance: Uint128,
+    #[serde(default)]
+    pub policy_totals: Vec<PolicyTotal>,
+}
+
+// how allocations reach the user
+// Push -- minted to the user's address as soon as they are allocated
+// Claim -- added to a claimable balance the user withdraws with HandleMsg::Claim
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ClaimMode {
+    Push,
+    Claim,
+}
+
+// limits on allocations in one reward token, None leaves a limit off
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct Caps {
+    pub max_per_allocation: Option<Uint128>,  // total of an allocation_id over all creds
+    pub max_per_user: Option<Uint128>,  // a single cred's share of an allocation_id
+    pub max_per_window: Option<Uint128>,  // total allocated within the last window_seconds
+    pub window_seconds: u64,
+}
+
+// whether an address or alias can be registered to only one cred_id at a
+// time, tightening it leaves existing duplicates in place
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Uniqueness {
+    pub addresses: bool,
+    pub aliases: bool,
+}
+
+impl Default for Uniqueness {
+    fn default() -> Self {
+        Self { addresses: true, aliases: true }
+    }
+}
+
+// circuit breakers set by the pauser
+// Normal -- everything is enabled
+// StopAllocations -- no allocations
+// StopRegistrations -- no registrations or changes to registered creds
+// StopAll -- nothing but changing the status, keys, roles and owner
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ContractStatus {
+    Normal,
+    StopAllocations,
+    StopRegistrations,
+    StopAll,
+}
+
+impl ContractStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ContractStatus::Normal => "normal",
+            ContractStatus::StopAllocations => "stop_allocations",
+            ContractStatus::StopRegistrations => "stop_registrations",
+            ContractStatus::StopAll => "stop_all",
+        }
+    }
+}
+
+// how payouts are funded
+// Mint -- minted by the token contract, which has this contract as a minter
+// Transfer -- transferred out of a treasury funded by sending tokens to this contract
+// Native -- sent as uscrt out of a treasury the owner funds with HandleMsg::Fund
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutMode {
+    Mint,
+    Transfer,
+    Native,
 }
 
 // struct containing token contract info
@@ -27,6 +322,31 @@ pub struct ContractInfo {
     pub address: HumanAddr,
 }
 
+// roles granted by the owner, the owner implicitly holds every role
+// Owner -- contract administration, held only by State.owner
+// Registrar -- registers users
+// Allocator -- allocates cred
+// Pauser -- stops and resumes the contract
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Owner,
+    Registrar,
+    Allocator,
+    Pauser,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Role::Owner => "owner",
+            Role::Registrar => "registrar",
+            Role::Allocator => "allocator",
+            Role::Pauser => "pauser",
+        }
+    }
+}
+
 pub fn config<S: Storage>(storage: &mut S) -> Singleton<S, State> {
     singleton(storage, CONFIG_KEY)
 }
@@ -40,14 +360,135 @@ pub struct UserCred {
     pub cred_id: String,  // ID on source cred
     pub scrt_address: CanonicalAddr,  //  public address of registered user
     pub total_allocated: Uint128,  // total allocated
-    pub allocations: Vec<Allocation>,
+    #[serde(default)]
+    pub claimable: Uint128,  // allocated but not yet claimed, only used in ClaimMode::Claim
+    #[serde(default)]
+    pub claimed: Uint128,  // total claimed
     pub alias: Option<String>,  // Optionally an alias
+    #[serde(default)]
+    pub token_balances: Vec<TokenBalance>,  // balances in reward tokens besides the default one
+    #[serde(default)]
+    pub debt: Uint128,  // paid out for revoked allocations, offset against future ones
+    // allocations stored inline before they moved to their own bucket, kept
+    // until MigrateAllocations moves them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allocations: Vec<Allocation>,
 }
 
+impl UserCred {
+    pub fn balance(&self, token: &Option<HumanAddr>) -> TokenBalance {
+        match token {
+            None => TokenBalance {
+                token: None,
+                total_allocated: self.total_allocated,
+                claimable: self.claimable,
+                claimed: self.claimed,
+                debt: self.debt,
+            },
+            Some(_) => self.token_balances
+                .iter()
+                .find(|balance| &balance.token == token)
+                .cloned()
+                .unwrap_or(TokenBalance { token: token.clone(), ..TokenBalance::default() }),
+        }
+    }
+
+    pub fn set_balance(&mut self, balance: TokenBalance) {
+        if balance.token.is_none() {
+            self.total_allocated = balance.total_allocated;
+            self.claimable = balance.claimable;
+            self.claimed = balance.claimed;
+            self.debt = balance.debt;
+            return;
+        }
+        match self.token_balances.iter_mut().find(|existing| existing.token == balance.token) {
+            Some(existing) => *existing = balance,
+            None => self.token_balances.push(balance),
+        }
+    }
+}
+
+// a cred's UserCred amounts in one reward token, token is None for the default token
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct TokenBalance {
+    pub token: Option<HumanAddr>,
+    pub total_allocated: Uint128,
+    pub claimable: Uint128,
+    pub claimed: Uint128,
+    #[serde(default)]
+    pub debt: Uint128,
+}
+
+impl TokenBalance {
+    // takes what is owed for revoked allocations out of amount, returning the rest
+    pub fn offset_debt(&mut self, amount: Uint128) -> Uint128 {
+        let offset = std::cmp::min(self.debt, amount);
+        self.debt = Uint128(self.debt.u128() - offset.u128());
+        Uint128(amount.u128() - offset.u128())
+    }
+}
+
+// Balanced -- vests on State.balanced_vesting, paid right away while that is None
+// Immediate -- paid right away
+// Vesting -- vests on the terms it carries
+// Recent -- SourceCred's RECENT policy, cred discounted by discount per period, paid right away
+// Special -- SourceCred's SPECIAL policy, a one-off grant to recipient, paid right away
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub enum PolicyType {
     Balanced,
     Immediate,
+    Vesting(VestingTerms),
+    Recent { discount: Decimal },
+    Special { memo: String, recipient: String },
+}
+
+impl PolicyType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            PolicyType::Balanced => "balanced",
+            PolicyType::Immediate => "immediate",
+            PolicyType::Vesting(_) => "vesting",
+            PolicyType::Recent { .. } => "recent",
+            PolicyType::Special { .. } => "special",
+        }
+    }
+}
+
+// linear vesting from the time of allocation, nothing is released before
+// cliff seconds have passed and everything once duration seconds have
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct VestingTerms {
+    pub cliff: u64,
+    pub duration: u64,
+}
+
+// an allocation vesting to a cred_id, vested amounts are released to the
+// cred's claimable balance when it claims
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct VestingSchedule {
+    pub allocation_id: String,
+    pub token: Option<HumanAddr>,
+    pub amount: Uint128,
+    pub start: u64,
+    pub terms: VestingTerms,
+    pub released: Uint128,
+}
+
+impl VestingSchedule {
+    pub fn vested(&self, time: u64) -> Uint128 {
+        let elapsed = time.saturating_sub(self.start);
+        if elapsed < self.terms.cliff {
+            return Uint128::zero();
+        }
+        if elapsed >= self.terms.duration {
+            return self.amount;
+        }
+        // amount * elapsed / duration, split so large amounts can't overflow,
+        // Uint128::multiply_ratio multiplies the full amount first
+        let duration = self.terms.duration as u128;
+        let (whole, rest) = (self.amount.u128() / duration, self.amount.u128() % duration);
+        Uint128(whole * elapsed as u128 + rest * elapsed as u128 / duration)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
@@ -55,6 +496,10 @@ pub struct Allocation {
     pub policy: PolicyType,
     pub amount: Uint128,
     pub allocation_id: String,
+    #[serde(default)]
+    pub token: Option<HumanAddr>,  // reward token paid in, None for the default token
+    #[serde(default)]
+    pub in_distribution: bool,  // counted towards the distribution of allocation_id
 }
 
 impl PartialEq for Allocation {
@@ -70,3 +515,317 @@ pub fn user_cred<S: Storage>(storage: &mut S) -> Bucket<S, UserCred> {
 pub fn user_cred_read<S: Storage>(storage: &S) -> ReadonlyBucket<S, UserCred> {
     bucket_read(USER_CRED_KEY, storage)
 }
+
+// UserCred of a deregistered cred_id, restored when it registers again so its
+// totals, and what it owes for revoked allocations, carry over
+pub fn deregistered_cred<S: Storage>(storage: &mut S) -> Bucket<S, UserCred> {
+    bucket(DEREGISTERED_CRED_KEY, storage)
+}
+
+pub fn deregistered_cred_read<S: Storage>(storage: &S) -> ReadonlyBucket<S, UserCred> {
+    bucket_read(DEREGISTERED_CRED_KEY, storage)
+}
+
+// times a cred_id has registered, an identity attestation is only good for
+// the count it was signed over
+pub fn registrations<S: Storage>(storage: &mut S) -> Bucket<S, u64> {
+    bucket(REGISTRATIONS_KEY, storage)
+}
+
+pub fn registrations_read<S: Storage>(storage: &S) -> ReadonlyBucket<S, u64> {
+    bucket_read(REGISTRATIONS_KEY, storage)
+}
+
+// allocations of a cred_id, keyed by allocation_id
+pub fn allocations<'a, S: Storage>(storage: &'a mut S, cred_id: &str) -> Bucket<'a, S, Allocation> {
+    Bucket::multilevel(&[ALLOCATIONS_KEY, cred_id.as_bytes()], storage)
+}
+
+pub fn allocations_read<'a, S: Storage>(storage: &'a S, cred_id: &str) -> ReadonlyBucket<'a, S, Allocation> {
+    ReadonlyBucket::multilevel(&[ALLOCATIONS_KEY, cred_id.as_bytes()], storage)
+}
+
+// cred_ids registered to a canonical address, several when addresses aren't
+// unique
+pub fn address_cred<S: Storage>(storage: &mut S) -> Bucket<S, Vec<String>> {
+    bucket(ADDRESS_CRED_KEY, storage)
+}
+
+pub fn address_cred_read<S: Storage>(storage: &S) -> ReadonlyBucket<S, Vec<String>> {
+    bucket_read(ADDRESS_CRED_KEY, storage)
+}
+
+// total allocated to a cred_id which has not registered an address yet,
+// released to the user on registration
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct Escrow {
+    pub total: Uint128,
+    #[serde(default)]
+    pub tokens: Vec<TokenAmount>,  // escrowed in reward tokens besides the default one
+}
+
+impl Escrow {
+    pub fn amount(&self, token: &Option<HumanAddr>) -> Uint128 {
+        match token {
+            None => self.total,
+            Some(_) => self.tokens
+                .iter()
+                .find(|escrowed| &escrowed.token == token)
+                .map(|escrowed| escrowed.amount)
+                .unwrap_or_else(Uint128::zero),
+        }
+    }
+
+    pub fn sub(&mut self, token: &Option<HumanAddr>, amount: Uint128) -> StdResult<()> {
+        if token.is_none() {
+            self.total = (self.total - amount)?;
+            return Ok(());
+        }
+        match self.tokens.iter_mut().find(|escrowed| &escrowed.token == token) {
+            Some(escrowed) => escrowed.amount = (escrowed.amount - amount)?,
+            None => return Err(StdError::generic_err("Nothing escrowed in this token")),
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, token: &Option<HumanAddr>, amount: Uint128) {
+        if token.is_none() {
+            self.total += amount;
+            return;
+        }
+        match self.tokens.iter_mut().find(|escrowed| &escrowed.token == token) {
+            Some(escrowed) => escrowed.amount += amount,
+            None => self.tokens.push(TokenAmount { token: token.clone(), amount }),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TokenAmount {
+    pub token: Option<HumanAddr>,
+    pub amount: Uint128,
+}
+
+pub fn escrow<S: Storage>(storage: &mut S) -> Bucket<S, Escrow> {
+    bucket(ESCROW_KEY, storage)
+}
+
+pub fn escrow_read<S: Storage>(storage: &S) -> ReadonlyBucket<S, Escrow> {
+    bucket_read(ESCROW_KEY, storage)
+}
+
+// addresses holding a role, keyed by Role::as_str
+pub fn role_holders<S: Storage>(storage: &mut S) -> Bucket<S, Vec<CanonicalAddr>> {
+    bucket(ROLES_KEY, storage)
+}
+
+pub fn role_holders_read<S: Storage>(storage: &S) -> ReadonlyBucket<S, Vec<CanonicalAddr>> {
+    bucket_read(ROLES_KEY, storage)
+}
+
+// hash of the seed given on init, kept out of State so Config doesn't expose it
+pub fn prng_seed<S: Storage>(storage: &mut S) -> Singleton<S, Binary> {
+    singleton(storage, PRNG_SEED_KEY)
+}
+
+pub fn prng_seed_read<S: Storage>(storage: &S) -> ReadonlySingleton<S, Binary> {
+    singleton_read(storage, PRNG_SEED_KEY)
+}
+
+// hashed viewing key of a canonical address
+pub fn viewing_key<S: Storage>(storage: &mut S) -> Bucket<S, Binary> {
+    bucket(VIEWING_KEY_KEY, storage)
+}
+
+pub fn viewing_key_read<S: Storage>(storage: &S) -> ReadonlyBucket<S, Binary> {
+    bucket_read(VIEWING_KEY_KEY, storage)
+}
+
+// permit names revoked by a canonical address
+pub fn revoked_permits<'a, S: Storage>(storage: &'a mut S, address: &CanonicalAddr) -> Bucket<'a, S, bool> {
+    Bucket::multilevel(&[REVOKED_PERMITS_KEY, address.as_slice()], storage)
+}
+
+pub fn revoked_permits_read<'a, S: Storage>(storage: &'a S, address: &CanonicalAddr) -> ReadonlyBucket<'a, S, bool> {
+    ReadonlyBucket::multilevel(&[REVOKED_PERMITS_KEY, address.as_slice()], storage)
+}
+
+// total allocated in a reward token under an allocation_id over all creds
+pub fn allocation_totals<'a, S: Storage>(storage: &'a mut S, token: &Option<HumanAddr>) -> Bucket<'a, S, Uint128> {
+    match token {
+        None => bucket(ALLOCATION_TOTALS_KEY, storage),
+        Some(token) => Bucket::multilevel(&[TOKEN_ALLOCATION_TOTALS_KEY, token.as_str().as_bytes()], storage),
+    }
+}
+
+pub fn allocation_totals_read<'a, S: Storage>(storage: &'a S, token: &Option<HumanAddr>) -> ReadonlyBucket<'a, S, Uint128> {
+    match token {
+        None => bucket_read(ALLOCATION_TOTALS_KEY, storage),
+        Some(token) => ReadonlyBucket::multilevel(&[TOKEN_ALLOCATION_TOTALS_KEY, token.as_str().as_bytes()], storage),
+    }
+}
+
+// an amount allocated in a reward token at a block time, kept while it counts
+// towards the token's Caps.max_per_window
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WindowEntry {
+    pub time: u64,
+    pub amount: Uint128,
+    #[serde(default)]
+    pub token: Option<HumanAddr>,
+}
+
+pub fn cap_window<S: Storage>(storage: &mut S) -> Singleton<S, Vec<WindowEntry>> {
+    singleton(storage, CAP_WINDOW_KEY)
+}
+
+pub fn cap_window_read<S: Storage>(storage: &S) -> ReadonlySingleton<S, Vec<WindowEntry>> {
+    singleton_read(storage, CAP_WINDOW_KEY)
+}
+
+// a SourceCred distribution, allocations whose allocation_id is the
+// distribution id are checked against its policy, token and budget
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Distribution {
+    pub id: String,
+    pub policy: PolicyType,
+    #[serde(default)]
+    pub token: Option<HumanAddr>,  // reward token the budget is in, None for the default token
+    pub budget: Uint128,
+    pub timestamp: u64,  // when the distribution was computed off-chain
+    pub allocated: Uint128,
+    pub recipients: u64,
+    pub finalized: bool,  // no further allocations once finalized
+}
+
+pub fn distributions<S: Storage>(storage: &mut S) -> Bucket<S, Distribution> {
+    bucket(DISTRIBUTIONS_KEY, storage)
+}
+
+pub fn distributions_read<S: Storage>(storage: &S) -> ReadonlyBucket<S, Distribution> {
+    bucket_read(DISTRIBUTIONS_KEY, storage)
+}
+
+// vesting schedules of a cred_id
+pub fn vesting<S: Storage>(storage: &mut S) -> Bucket<S, Vec<VestingSchedule>> {
+    bucket(VESTING_KEY, storage)
+}
+
+pub fn vesting_read<S: Storage>(storage: &S) -> ReadonlyBucket<S, Vec<VestingSchedule>> {
+    bucket_read(VESTING_KEY, storage)
+}
+
+// a revoked allocation, debt is the part that had already been paid out
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Revocation {
+    pub allocation_id: String,
+    pub token: Option<HumanAddr>,
+    pub amount: Uint128,
+    pub debt: Uint128,
+    pub reason: String,
+    pub revoked_by: HumanAddr,
+    pub time: u64,
+}
+
+// revocations of a cred_id, oldest first, keyed by big-endian position
+pub fn revocations<'a, S: Storage>(storage: &'a mut S, cred_id: &str) -> Bucket<'a, S, Revocation> {
+    Bucket::multilevel(&[REVOCATIONS_KEY, cred_id.as_bytes()], storage)
+}
+
+pub fn revocations_read<'a, S: Storage>(storage: &'a S, cred_id: &str) -> ReadonlyBucket<'a, S, Revocation> {
+    ReadonlyBucket::multilevel(&[REVOCATIONS_KEY, cred_id.as_bytes()], storage)
+}
+
+// entries in the revocations of a cred_id
+pub fn revocations_indexed<S: Storage>(storage: &mut S) -> Bucket<S, u64> {
+    bucket(REVOCATIONS_INDEXED_KEY, storage)
+}
+
+pub fn revocations_indexed_read<S: Storage>(storage: &S) -> ReadonlyBucket<S, u64> {
+    bucket_read(REVOCATIONS_INDEXED_KEY, storage)
+}
+
+// a distribution published as the merkle root of its receipts, each leaf is
+// the sha256 of a msg::Receipt's JSON, users claim their share with a proof
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MerkleRoot {
+    pub distribution_id: String,
+    pub merkle_root: Binary,
+    pub token: Option<HumanAddr>,
+    pub total: Uint128,
+    pub claimed: Uint128,
+}
+
+pub fn merkle_roots<S: Storage>(storage: &mut S) -> Bucket<S, MerkleRoot> {
+    bucket(MERKLE_ROOTS_KEY, storage)
+}
+
+pub fn merkle_roots_read<S: Storage>(storage: &S) -> ReadonlyBucket<S, MerkleRoot> {
+    bucket_read(MERKLE_ROOTS_KEY, storage)
+}
+
+// cred_ids which claimed their leaf of a distribution root, kept when the
+// allocation the claim recorded is revoked so the leaf can't be claimed again
+pub fn root_claims<'a, S: Storage>(storage: &'a mut S, distribution_id: &str) -> Bucket<'a, S, bool> {
+    Bucket::multilevel(&[ROOT_CLAIMS_KEY, distribution_id.as_bytes()], storage)
+}
+
+pub fn root_claims_read<'a, S: Storage>(storage: &'a S, distribution_id: &str) -> ReadonlyBucket<'a, S, bool> {
+    ReadonlyBucket::multilevel(&[ROOT_CLAIMS_KEY, distribution_id.as_bytes()], storage)
+}
+
+// cred_ids in the order they registered, keyed by big-endian position,
+// entries of deregistered creds stay behind and are skipped when listing
+pub fn user_index<S: Storage>(storage: &mut S) -> Bucket<S, String> {
+    bucket(USER_INDEX_KEY, storage)
+}
+
+pub fn user_index_read<S: Storage>(storage: &S) -> ReadonlyBucket<S, String> {
+    bucket_read(USER_INDEX_KEY, storage)
+}
+
+// position of a registered cred_id in user_index
+pub fn user_position<S: Storage>(storage: &mut S) -> Bucket<S, u64> {
+    bucket(USER_POSITION_KEY, storage)
+}
+
+pub fn user_position_read<S: Storage>(storage: &S) -> ReadonlyBucket<S, u64> {
+    bucket_read(USER_POSITION_KEY, storage)
+}
+
+// allocation_ids of a cred_id in the order they were allocated, keyed by
+// big-endian position, entries of revoked allocations are skipped when listing
+pub fn allocation_index<'a, S: Storage>(storage: &'a mut S, cred_id: &str) -> Bucket<'a, S, String> {
+    Bucket::multilevel(&[ALLOCATION_INDEX_KEY, cred_id.as_bytes()], storage)
+}
+
+pub fn allocation_index_read<'a, S: Storage>(storage: &'a S, cred_id: &str) -> ReadonlyBucket<'a, S, String> {
+    ReadonlyBucket::multilevel(&[ALLOCATION_INDEX_KEY, cred_id.as_bytes()], storage)
+}
+
+// position of an allocation_id of a cred_id in allocation_index
+pub fn allocation_position<'a, S: Storage>(storage: &'a mut S, cred_id: &str) -> Bucket<'a, S, u64> {
+    Bucket::multilevel(&[ALLOCATION_POSITION_KEY, cred_id.as_bytes()], storage)
+}
+
+pub fn allocation_position_read<'a, S: Storage>(storage: &'a S, cred_id: &str) -> ReadonlyBucket<'a, S, u64> {
+    ReadonlyBucket::multilevel(&[ALLOCATION_POSITION_KEY, cred_id.as_bytes()], storage)
+}
+
+// entries in the allocation_index of a cred_id
+pub fn allocations_indexed<S: Storage>(storage: &mut S) -> Bucket<S, u64> {
+    bucket(ALLOCATIONS_INDEXED_KEY, storage)
+}
+
+pub fn allocations_indexed_read<S: Storage>(storage: &S) -> ReadonlyBucket<S, u64> {
+    bucket_read(ALLOCATIONS_INDEXED_KEY, storage)
+}
+
+// cred_ids registered with an alias, at most one while aliases are unique
+pub fn alias_creds<S: Storage>(storage: &mut S) -> Bucket<S, Vec<String>> {
+    bucket(ALIAS_CRED_KEY, storage)
+}
+
+pub fn alias_creds_read<S: Storage>(storage: &S) -> ReadonlyBucket<S, Vec<String>> {
+    bucket_read(ALIAS_CRED_KEY, storage)
+}
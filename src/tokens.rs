@@ -1,24 +1,101 @@
-use cosmwasm_std::{
-    Binary, CosmosMsg, HumanAddr, StdResult, Storage, Uint128, WasmMsg,
-};
-
-use crate::state::{ config_read };
-
-pub fn mint<S: Storage>(store: &S, amount: Uint128, account: HumanAddr) -> StdResult<CosmosMsg> {
-    let constants = config_read(store).load()?;
-
-    return Ok(CosmosMsg::Wasm(WasmMsg::Execute {
-        contract_addr: constants.token_contract.address,
-        callback_code_hash: constants.token_contract.code_hash,
-        msg: Binary(
-            format!(
-                r#"{{"mint": {{"address":"{}", "amount":"{}"}} }}"#,
-                account.to_string(),
-                amount.to_string()
-            )
-            .as_bytes()
-            .to_vec(),
-        ),
-        send: vec![],
-    }));
+use cosmwasm_std::{coins, BankMsg, CosmosMsg, HumanAddr, StdError, StdResult, Uint128};
+
+use crate::snip20::{self, MintAction, TransferAction, BLOCK_SIZE};
+use crate::state::{PayoutMode, State};
+
+pub const NATIVE_DENOM: &str = "uscrt";
+
+// pays amount of token to recipient, minting it or sending it out of the
+// token's treasury depending on the payout mode
+pub fn payout(
+    state: &mut State,
+    token: &Option<HumanAddr>,
+    amount: Uint128,
+    recipient: HumanAddr,
+    memo: Option<String>,
+) -> StdResult<CosmosMsg> {
+    let msg = match state.payout_mode {
+        PayoutMode::Mint => snip20::HandleMsg::Mint {
+            recipient,
+            amount,
+            memo,
+            padding: None,
+        },
+        PayoutMode::Transfer => {
+            withdraw_treasury(state, token, amount)?;
+            snip20::HandleMsg::Transfer {
+                recipient,
+                amount,
+                memo,
+                padding: None,
+            }
+        }
+        PayoutMode::Native => {
+            withdraw_treasury(state, token, amount)?;
+            return Ok(bank_send(state, recipient, amount));
+        }
+    };
+
+    msg.to_cosmos_msg(BLOCK_SIZE, state.token_contract(token)?)
+}
+
+// native coins have no batch send, so Native mode pays with one message per recipient
+pub fn batch_payout(
+    state: &mut State,
+    token: &Option<HumanAddr>,
+    actions: Vec<(HumanAddr, Uint128)>,
+    memo: Option<String>,
+) -> StdResult<Vec<CosmosMsg>> {
+    let msg = match state.payout_mode {
+        PayoutMode::Mint => snip20::HandleMsg::BatchMint {
+            actions: actions
+                .into_iter()
+                .map(|(recipient, amount)| MintAction { recipient, amount, memo: memo.clone() })
+                .collect(),
+            padding: None,
+        },
+        PayoutMode::Transfer => {
+            withdraw_treasury(state, token, total_of(&actions))?;
+
+            snip20::HandleMsg::BatchTransfer {
+                actions: actions
+                    .into_iter()
+                    .map(|(recipient, amount)| TransferAction { recipient, amount, memo: memo.clone() })
+                    .collect(),
+                padding: None,
+            }
+        }
+        PayoutMode::Native => {
+            withdraw_treasury(state, token, total_of(&actions))?;
+            return Ok(actions
+                .into_iter()
+                .map(|(recipient, amount)| bank_send(state, recipient, amount))
+                .collect());
+        }
+    };
+
+    Ok(vec![msg.to_cosmos_msg(BLOCK_SIZE, state.token_contract(token)?)?])
+}
+
+fn bank_send(state: &State, recipient: HumanAddr, amount: Uint128) -> CosmosMsg {
+    CosmosMsg::Bank(BankMsg::Send {
+        from_address: state.contract_address.clone(),
+        to_address: recipient,
+        amount: coins(amount.u128(), NATIVE_DENOM),
+    })
+}
+
+fn total_of(actions: &[(HumanAddr, Uint128)]) -> Uint128 {
+    let mut total = Uint128::zero();
+    for (_, amount) in actions.iter() {
+        total += *amount;
+    }
+    total
+}
+
+fn withdraw_treasury(state: &mut State, token: &Option<HumanAddr>, amount: Uint128) -> StdResult<()> {
+    let mut totals = state.totals(token)?;
+    totals.treasury_balance = (totals.treasury_balance - amount)
+        .map_err(|_| StdError::generic_err("Insufficient treasury balance"))?;
+    state.set_totals(token, totals)
 }
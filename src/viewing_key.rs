@@ -0,0 +1,71 @@
+use std::fmt;
+
+use cosmwasm_std::{Binary, Env};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::sha_256;
+
+pub const VIEWING_KEY_PREFIX: &str = "api_key_";
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct ViewingKey(pub String);
+
+impl ViewingKey {
+    // derives a new key from the contract's secret seed, the block, the
+    // sender and entropy supplied by the sender
+    pub fn new(env: &Env, seed: &[u8], entropy: &[u8]) -> Self {
+        let mut key_material = seed.to_vec();
+        key_material.extend_from_slice(&env.block.height.to_be_bytes());
+        key_material.extend_from_slice(&env.block.time.to_be_bytes());
+        key_material.extend_from_slice(env.message.sender.as_str().as_bytes());
+        key_material.extend_from_slice(entropy);
+
+        let key = sha_256(&key_material);
+        ViewingKey(VIEWING_KEY_PREFIX.to_string() + &Binary(key.to_vec()).to_base64())
+    }
+
+    pub fn to_hashed(&self) -> [u8; 32] {
+        sha_256(self.0.as_bytes())
+    }
+
+    pub fn check_viewing_key(&self, hashed_key: &[u8]) -> bool {
+        ct_slice_compare(&self.to_hashed(), hashed_key)
+    }
+}
+
+impl fmt::Display for ViewingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// compares without returning early, so the time taken doesn't leak how much
+// of a guessed key was right
+pub fn ct_slice_compare(s1: &[u8], s2: &[u8]) -> bool {
+    if s1.len() != s2.len() {
+        return false;
+    }
+    s1.iter().zip(s2.iter()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmwasm_std::testing::mock_env;
+
+    #[test]
+    fn viewing_key_works() {
+        let env = mock_env("secret007", &[]);
+        let key = ViewingKey::new(&env, b"seed", b"entropy");
+        assert!(key.0.starts_with(VIEWING_KEY_PREFIX));
+
+        let hashed = key.to_hashed();
+        assert!(key.check_viewing_key(&hashed));
+        assert!(!ViewingKey("wrong".to_string()).check_viewing_key(&hashed));
+
+        // different entropy, different key
+        let other = ViewingKey::new(&env, b"seed", b"other entropy");
+        assert!(!other.check_viewing_key(&hashed));
+    }
+}